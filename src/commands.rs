@@ -0,0 +1,164 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cpu::disassembler::{disassemble_range, format_listing, jump_labels};
+use crate::cpu::symbols::Symbols;
+use crate::link_cable::LinkCable;
+use crate::useful_func::parse_hex;
+use crate::{Emulator, Printer, StdoutDevice, TraceStart};
+
+const BANK_SIZE: usize = 0x4000;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+/// Applies the options given when starting the emulator :
+///
+/// `--trace <file>` logs every instruction, gameboy-doctor style.
+/// `--trace-after <n>` or `--trace-pc <addr>` delay the start of the trace.
+/// `--trace-max <n>` stops it after n lines.
+/// `--trace-labels` marks lines at a symbol from `<rom>.sym` with its label.
+/// `--gdb <port>` waits for GDB to connect on that local port.
+/// `--serial stdout` prints what the game sends over the link port.
+/// `--serial printer` plugs in a Game Boy Printer, printouts go to `print_<n>.png`.
+/// `--link-listen <port|path>` waits for another emulator to plug a link cable in,
+/// `--link-connect <host:port|path>` plugs into one. A path is a Unix socket.
+pub fn configure(emu: &mut Emulator, args: &[String]) -> io::Result<()> {
+    let usage = "usage : [--trace <file> [--trace-after <n> | --trace-pc <addr>] [--trace-max <n>] [--trace-labels]] [--gdb <port>] [--serial stdout|printer | --link-listen <port|path> | --link-connect <host:port|path>]";
+
+    let mut trace_path = None;
+    let mut trace_start = TraceStart::Immediately;
+    let mut trace_max = None;
+    let mut trace_labels = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace_path = Some(PathBuf::from(args.next().ok_or_else(|| invalid(usage))?)),
+            "--trace-after" => {
+                let count = args.next().and_then(|n| n.parse().ok());
+                trace_start = TraceStart::AfterInstructions(count.ok_or_else(|| invalid(usage))?);
+            }
+            "--trace-pc" => {
+                let address = args.next().and_then(|a| parse_hex(a));
+                trace_start = TraceStart::AtPc(address.ok_or_else(|| invalid(usage))?);
+            }
+            "--trace-max" => {
+                let max = args.next().and_then(|n| n.parse().ok());
+                trace_max = Some(max.ok_or_else(|| invalid(usage))?);
+            }
+            "--trace-labels" => trace_labels = true,
+            "--gdb" => {
+                let port = args.next().and_then(|p| p.parse().ok());
+                emu.listen_gdb(port.ok_or_else(|| invalid(usage))?)?;
+            }
+            "--serial" => match args.next().map(|device| device.as_str()) {
+                Some("stdout") => emu.connect_serial(Box::new(StdoutDevice)),
+                Some("printer") => emu.connect_serial(Box::new(Printer::new(Path::new(".")))),
+                _ => return Err(invalid(usage)),
+            },
+            "--link-listen" => {
+                let address = args.next().ok_or_else(|| invalid(usage))?;
+                emu.connect_serial(Box::new(LinkCable::listen(address)?));
+            }
+            "--link-connect" => {
+                let address = args.next().ok_or_else(|| invalid(usage))?;
+                emu.connect_serial(Box::new(LinkCable::connect(address)?));
+            }
+            _ => return Err(invalid(usage)),
+        }
+    }
+
+    if let Some(path) = trace_path {
+        emu.trace_to(&path, trace_start, trace_max, trace_labels)?;
+        println!("Tracing instructions to {:?}", path);
+    }
+    Ok(())
+}
+
+/// `disasm <rom> [--bank <n>] [--start <addr>] [--end <addr>]`
+///
+/// Prints a ROM as assembly, one bank at a time. Bank 0 sits at 0000-3FFF and every
+/// other bank at 4000-7FFF, so addresses match what the CPU sees. Labels come from
+/// `<rom>.sym` when there is one.
+pub fn disasm(args: &[String]) -> io::Result<()> {
+    let usage = "usage : disasm <rom> [--bank <n>] [--start <addr>] [--end <addr>]";
+
+    let mut rom_path = None;
+    let mut bank = None;
+    let mut start = None;
+    let mut end = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" => {
+                bank = Some(
+                    args.next()
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .ok_or_else(|| invalid(usage))?,
+                )
+            }
+            "--start" => {
+                start = Some(
+                    args.next()
+                        .and_then(|a| parse_hex(a))
+                        .ok_or_else(|| invalid(usage))?,
+                )
+            }
+            "--end" => {
+                end = Some(
+                    args.next()
+                        .and_then(|a| parse_hex(a))
+                        .ok_or_else(|| invalid(usage))?,
+                )
+            }
+            _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(invalid(usage)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or_else(|| invalid(usage))?;
+    let rom = fs::read(&rom_path)?;
+
+    // labels from an RGBDS build take the place of the generated ones.
+    let mut symbols = Symbols::new();
+    let sym_path = rom_path.with_extension("sym");
+    if sym_path.exists() {
+        symbols.load(&sym_path)?;
+    }
+    let bank_count = rom.len().div_ceil(BANK_SIZE);
+
+    let banks = match bank {
+        Some(bank) if bank >= bank_count => {
+            return Err(invalid(&format!("the ROM only has {} banks", bank_count)))
+        }
+        Some(bank) => bank..bank + 1,
+        None => 0..bank_count,
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for bank in banks {
+        let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+        let first = start.unwrap_or(base).max(base);
+        let last = end.unwrap_or(base + 0x3FFF).min(base + 0x3FFF);
+        if first > last {
+            continue;
+        }
+
+        let read = |address: u16| {
+            let offset = bank * BANK_SIZE + (address - base) as usize;
+            rom.get(offset).copied().unwrap_or(0xFF)
+        };
+        let lines = disassemble_range(first, last, read);
+        let mut labels = jump_labels(&lines);
+        labels.extend(symbols.in_bank(bank as u16));
+
+        writeln!(out, "; bank {} : {:04X}-{:04X}", bank, first, last)?;
+        writeln!(out, "{}", format_listing(&lines, &labels))?;
+    }
+    Ok(())
+}
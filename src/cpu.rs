@@ -4,17 +4,25 @@ use flags_register::FlagsRegister;
 use instruction::Instruction;
 use memory_bus::MemoryBus;
 use registers::Registers;
+use state::*;
+use std::io;
 use target::*;
-use timer::*;
 
+pub mod cheats;
 pub mod clock;
+pub mod disassembler;
 pub mod flags_register;
 pub mod instruction;
 pub mod memory_bus;
 pub mod memory_map;
 pub mod registers;
+pub mod serial;
+pub mod state;
+pub mod symbols;
 pub mod target;
 pub mod timer;
+pub mod trace;
+pub mod watchpoint;
 
 #[derive(Debug)]
 pub struct CPU {
@@ -25,10 +33,16 @@ pub struct CPU {
     pub pc: u16,
     pub sp: u16,
     pub is_halted: bool,
+    // STOP's low power mode, left when a selected joypad line goes low.
+    pub is_stopped: bool,
+    // HALT with IME off and an interrupt pending : the next opcode is read twice.
+    halt_bug: bool,
     pub m: u8, // Internal Clock of Last Instruction
     pending_inrerupt_disabled: bool,
     pending_interupt_enabled: bool,
     once: bool,
+    pub tracer: Option<trace::Tracer>,
+    pub symbols: symbols::Symbols,
 }
 
 impl CPU {
@@ -41,10 +55,14 @@ impl CPU {
             pc: 0,
             sp: 0,
             is_halted: false,
+            is_stopped: false,
+            halt_bug: false,
             m: 0,
             pending_inrerupt_disabled: false,
             pending_interupt_enabled: false,
             once: false,
+            tracer: None,
+            symbols: symbols::Symbols::new(),
         }
     }
 
@@ -52,17 +70,85 @@ impl CPU {
         self.reset_cpu();
     }
 
+    /// Forgets a pending EI / DI and wakes from HALT, for tests that set registers directly.
+    pub fn clear_pending(&mut self) {
+        self.pending_inrerupt_disabled = false;
+        self.pending_interupt_enabled = false;
+        self.is_halted = false;
+        self.is_stopped = false;
+        self.halt_bug = false;
+    }
+
     pub fn reset_cpu(&mut self) {
         self.m = 0;
         self.pc = 0x0;
+        self.registers = Registers::new();
+        self.pending_inrerupt_disabled = false;
+        self.pending_interupt_enabled = false;
         self.registers.set_bc(0x0013);
         self.registers.set_de(0x00D8);
         self.registers.set_hl(0x014D);
         self.is_halted = false;
+        self.is_stopped = false;
+        self.halt_bug = false;
         self.sp = 0x0;
         self.bus.reset();
     }
 
+    /// Snapshot of everything except the catridge ROM itself.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(STATE_MAGIC);
+        w.u8(STATE_VERSION);
+        w.u8(self.registers.a);
+        w.u8(self.registers.b);
+        w.u8(self.registers.c);
+        w.u8(self.registers.d);
+        w.u8(self.registers.e);
+        w.u8(self.registers.f.con());
+        w.u8(self.registers.h);
+        w.u8(self.registers.l);
+        w.u16(self.pc);
+        w.u16(self.sp);
+        w.bool(self.is_halted);
+        w.bool(self.is_stopped);
+        w.bool(self.halt_bug);
+        w.u8(self.m);
+        w.bool(self.pending_inrerupt_disabled);
+        w.bool(self.pending_interupt_enabled);
+        self.bus.save_state(&mut w);
+        w.data
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0u8; 4];
+        r.bytes(&mut magic)?;
+        if &magic != STATE_MAGIC || r.u8()? != STATE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a RIP Boy save state",
+            ));
+        }
+        self.registers.a = r.u8()?;
+        self.registers.b = r.u8()?;
+        self.registers.c = r.u8()?;
+        self.registers.d = r.u8()?;
+        self.registers.e = r.u8()?;
+        self.registers.f = FlagsRegister::from(r.u8()?);
+        self.registers.h = r.u8()?;
+        self.registers.l = r.u8()?;
+        self.pc = r.u16()?;
+        self.sp = r.u16()?;
+        self.is_halted = r.bool()?;
+        self.is_stopped = r.bool()?;
+        self.halt_bug = r.bool()?;
+        self.m = r.u8()?;
+        self.pending_inrerupt_disabled = r.bool()?;
+        self.pending_interupt_enabled = r.bool()?;
+        self.bus.load_state(&mut r)
+    }
+
     fn _execute(&mut self, instruction: Instruction) -> u16 {
         let mut value;
         match instruction {
@@ -830,7 +916,13 @@ impl CPU {
             }
             Instruction::HALT => {
                 self.m += 4;
-                self.is_halted = true;
+                let pending = self.bus.peek(0xFF0F) & self.bus.peek(0xFFFF) & 0x1F != 0;
+                if pending && !self.bus.interupt_master {
+                    // doesn't halt at all, and PC sticks on the next opcode.
+                    self.halt_bug = true;
+                } else {
+                    self.is_halted = true;
+                }
                 self.pc.wrapping_add(1)
             }
 
@@ -951,10 +1043,10 @@ impl CPU {
             }
 
             Instruction::STOP => {
-                println!("Executing Stop Inst.");
                 self.m += 4;
                 self._stop();
-                self.pc.wrapping_add(1)
+                // the byte after STOP is skipped.
+                self.pc.wrapping_add(2)
             }
 
             Instruction::DI => {
@@ -2007,21 +2099,93 @@ impl CPU {
         self.sp = self.sp.wrapping_sub(1);
         self.bus.write_bytes(self.sp, (value & 0x00FF) as u8);
     }
+    fn trace_instruction(&mut self) {
+        let mut finished = false;
+        if let Some(tracer) = &mut self.tracer {
+            if tracer.wants(self.pc) {
+                let mut pcmem = [0; 4];
+                for (i, byte) in pcmem.iter_mut().enumerate() {
+                    *byte = self.bus.peek(self.pc.wrapping_add(i as u16));
+                }
+                let label = if tracer.labels {
+                    self.symbols.label(
+                        self.pc,
+                        self.bus.current_rom_bank() as u16,
+                        self.bus.current_ram_bank() as u16,
+                    )
+                } else {
+                    None
+                };
+                if let Err(error) = tracer.log(&self.registers, self.sp, self.pc, pcmem, label) {
+                    println!("Could not write trace : {}", error);
+                    finished = true;
+                }
+            }
+            finished |= tracer.is_finished();
+            if finished {
+                println!("Trace finished after {} lines", tracer.lines());
+            }
+        }
+        if finished {
+            self.tracer = None;
+        }
+    }
+
+    /// Runs one instruction along with the interupts, timers and graphics it clocks.
+    /// Returns true when the frame reached v-blank.
+    pub fn tick(&mut self) -> bool {
+        if self.is_stopped {
+            // the clock is off, only the screen keeps being refreshed.
+            self.m = 4;
+            return self.update_graphics(self.video_cycles() as i16);
+        }
+
+        self.step();
+        self.do_interupts();
+        self.update_timers(self.m as u32);
+        self.update_serial(self.m as u32);
+        self.update_graphics(self.video_cycles() as i16)
+    }
+
+    /// Cycles the PPU moved on by in the last `tick`. In double speed it runs at
+    /// half the CPU's rate, so frames are measured in these.
+    pub fn video_cycles(&self) -> u32 {
+        if self.bus.double_speed && !self.is_stopped {
+            self.m as u32 / 2
+        } else {
+            self.m as u32
+        }
+    }
+
     /// fetch - exectue the Opcode
     pub fn step(&mut self) {
         //tprintln!("doing fetch - exectue the Opcode");
         self.m = 0;
-        let mut instruction_byte = self.bus.read_byte(self.pc);
-
-        let prefixed = instruction_byte == 0xCB;
-        if prefixed {
+        self.bus.start_instruction();
+        self.bus.watch.pc = self.pc;
+        // EI and DI take effect once the instruction after them is done.
+        let enable_interupts = self.pending_interupt_enabled;
+        let disable_interupts = self.pending_inrerupt_disabled;
+
+        if self.is_halted {
+            // nothing is fetched until an interupt ends the halt.
             self.m += 4;
-            self.pc = self.pc.wrapping_add(1);
-            instruction_byte = self.bus.read_byte(self.pc);
-        }
+        } else {
+            self.trace_instruction();
+            let mut instruction_byte = self.bus.read_byte(self.pc);
+            if self.halt_bug {
+                // PC isn't incremented past the opcode, so it gets read again.
+                self.halt_bug = false;
+                self.pc = self.pc.wrapping_sub(1);
+            }
+
+            let prefixed = instruction_byte == 0xCB;
+            if prefixed {
+                self.m += 4;
+                self.pc = self.pc.wrapping_add(1);
+                instruction_byte = self.bus.read_byte(self.pc);
+            }
 
-        if !(self.is_halted) {
-            //tprintln!("cpu check 1");
             let next_pc =
                 if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {
                     self._execute(instruction)
@@ -2029,22 +2193,15 @@ impl CPU {
                     self.pc
                 };
             self.pc = next_pc;
-        } else {
-            self.m += 4;
         }
 
-        if self.pending_inrerupt_disabled {
-            if self.bus.read_byte(self.pc - 1) != 0xF3 {
-                self.pending_inrerupt_disabled = false;
-                self.bus.interupt_master = false;
-            }
+        if disable_interupts {
+            self.pending_inrerupt_disabled = false;
+            self.bus.interupt_master = false;
         }
-
-        if self.pending_interupt_enabled {
-            if self.bus.read_byte(self.pc - 1) != 0xFB {
-                self.pending_interupt_enabled = false;
-                self.bus.interupt_master = true;
-            }
+        if enable_interupts {
+            self.pending_interupt_enabled = false;
+            self.bus.interupt_master = true;
         }
     }
 
@@ -2196,7 +2353,14 @@ impl CPU {
         }
     }
 
-    fn _stop(&self) {}
+    /// With a speed switch armed through KEY1 on a CGB catridge, switches speed
+    /// instead of stopping. Either way DIV is reset.
+    fn _stop(&mut self) {
+        self.bus.timer.write_divider(&mut self.bus.memory);
+        if !self.bus.speed_switch() {
+            self.is_stopped = true;
+        }
+    }
 
     fn _jump(&mut self, should_jump: bool, exception: bool) -> u16 {
         if should_jump && !(exception) {
@@ -2225,6 +2389,7 @@ impl CPU {
     pub fn get_key_pressed(&mut self, key: u8) {
         if self.bus.key_pressed(key) {
             self._request_interupt(4);
+            self.is_stopped = false;
         }
         // self.update_graphics(self.m as i16);
     }
@@ -2233,63 +2398,56 @@ impl CPU {
         self.bus.key_released(key);
     }
 
+    /// Clocks the timer for the rest of the `cycles` the instruction took.
     pub fn update_timers(&mut self, cycles: u32) {
-        self.bus.do_divider_register(cycles);
-
-        if self.bus.clock_enabled() {
-            self.bus.mem_timer_counter = self.bus.mem_timer_counter.wrapping_sub(cycles as i32);
-
-            if self.bus.mem_timer_counter < 0 {
-                let remaining_cycles = self.bus.mem_timer_counter;
-                self.bus.set_clock_freq();
-                self.bus.mem_timer_counter += remaining_cycles;
-
-                let tma_val = self.bus.read_byte(TIMA as u16);
-                self.bus.write_bytes(TIMA as u16, tma_val + 1);
-                // overflow
-                if self.bus.read_byte(TIMA as u16) == 255 {
-                    let tma_val = self.bus.read_byte(TMA as u16);
-                    self.bus.write_bytes(TIMA as u16, tma_val);
-                    self._request_interupt(2);
-                    self.is_halted = false;
-                }
-            }
+        self.bus.update_timer(cycles);
+    }
+
+    pub fn update_serial(&mut self, cycles: u32) {
+        if self.bus.serial.update(&mut self.bus.memory, cycles) {
+            self._request_interupt(3);
         }
     }
 
+    /// An enabled interrupt being requested ends HALT whether IME is set or not.
+    /// With IME set, only the highest priority one (lowest bit) is dispatched.
     pub fn do_interupts(&mut self) {
-        if self.bus.interupt_master {
-            let req = self.bus.read_byte(0xFF0F);
-            let enabled = self.bus.read_byte(0xFFFF);
-
-            if req > 0 {
-                for i in 0..5 {
-                    if test_bit(req, i) {
-                        if test_bit(enabled, i) {
-                            self._service_interupt(i);
-                        }
-                    }
-                }
-            }
+        let pending = self.bus.peek(0xFF0F) & self.bus.peek(0xFFFF) & 0x1F;
+        if pending == 0 {
+            return;
         }
-    }
 
-    pub fn _service_interupt(&mut self, i: u8) {
         self.is_halted = false;
+        if self.bus.interupt_master {
+            self._service_interupt();
+        }
+    }
 
+    /// Takes 5 M-cycles : two waiting, two pushing PC, one jumping. Which interrupt
+    /// to jump to is only decided between the two pushes, so if the high byte of
+    /// PC lands on IE (SP was 0x0000) and clears the pending bit, nothing is
+    /// acknowledged and the CPU jumps to 0x0000 instead.
+    pub fn _service_interupt(&mut self) {
         self.bus.interupt_master = false;
-        let mut req = self.bus.read_byte(0xFF0F);
-        req = bit_reset(req, i);
-        self.bus.write_bytes(0xFF0F, req);
-
-        self._push(self.pc);
-
-        self.pc = match i {
-            0 => 0x40,
-            1 => 0x48,
-            2 => 0x50,
-            4 => 0x60,
-            _ => panic!("unhandled.. interupt bit"),
+        if self.halt_bug {
+            // EI then HALT : return to the HALT, which runs again.
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.bus.write_bytes(self.sp, (self.pc >> 8) as u8);
+        let pending = self.bus.peek(0xFF0F) & self.bus.peek(0xFFFF) & 0x1F;
+        self.sp = self.sp.wrapping_sub(1);
+        self.bus.write_bytes(self.sp, self.pc as u8);
+
+        self.pc = if pending == 0 {
+            0x0000
+        } else {
+            let i = pending.trailing_zeros() as u8;
+            self.bus.memory[0xFF0F] = bit_reset(self.bus.memory[0xFF0F], i);
+            // 0x40 vblank, 0x48 lcd stat, 0x50 timer, 0x58 serial, 0x60 joypad.
+            0x40 + i as u16 * 8
         };
         self.m += 20;
     }
@@ -2302,7 +2460,7 @@ impl CPU {
             self.bus.memory[0xFF44] = 0;
             status &= 252;
             status = bit_set(status, 0);
-            self.bus.write_bytes(0xFF41, status);
+            self.bus.poke(0xFF41, status);
             return;
         }
 
@@ -2355,7 +2513,7 @@ impl CPU {
             }
         }
 
-        if self.bus.read_byte(0xFF44) == self.bus.read_byte(0xFF45) {
+        if self.bus.peek(0xFF44) == self.bus.peek(0xFF45) {
             status = bit_set(status, 2);
             if test_bit(status, 6) {
                 self._request_interupt(1);
@@ -2363,17 +2521,17 @@ impl CPU {
         } else {
             status = bit_reset(status, 2);
         }
-        self.bus.write_bytes(0xFF41, status);
+        self.bus.poke(0xFF41, status);
     }
 
     pub fn _is_lcd_enabled(&mut self) -> bool {
-        let lcd_status = self.bus.read_byte(0xFF40);
+        let lcd_status = self.bus.peek(0xFF40);
         //tprintln!("check lcd_staus = {:08b} ", lcd_status);
         test_bit(lcd_status, 7)
     }
 
     pub fn _draw_scan_line(&mut self) {
-        let control = self.bus.read_byte(0xFF40);
+        let control = self.bus.peek(0xFF40);
 
         if test_bit(control, 0) {
             self.bus.render_tiles();
@@ -2394,7 +2552,7 @@ impl CPU {
 
         if self.bus.scan_line_counter <= 0 {
             self.bus.memory[0xFF44] = self.bus.memory[0xFF44].wrapping_add(1);
-            let current_line = self.bus.read_byte(0xFF44);
+            let current_line = self.bus.peek(0xFF44);
 
             self.bus.scan_line_counter = 456;
 
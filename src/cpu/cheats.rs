@@ -0,0 +1,212 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy)]
+pub enum CheatCode {
+    /// `TTVVLLHH` : RAM patch rewritten every frame. Types 0x80-0x83 target that
+    /// cartridge RAM bank, anything else writes through the bus.
+    GameShark { kind: u8, value: u8, address: u16 },
+    /// `VVA-AAA-CxC` : ROM read substitution, only when the ROM holds `compare`.
+    GameGenie { address: u16, value: u8, compare: Option<u8> },
+}
+
+fn hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+impl CheatCode {
+    pub fn parse(code: &str) -> Result<CheatCode, String> {
+        let digits: String = code.chars().filter(|c| *c != '-').collect();
+        let invalid = || format!("{:?} is not a GameShark or Game Genie code", code);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        match (digits.len(), code.contains('-')) {
+            (8, false) => {
+                let kind = hex(&digits[0..2]).ok_or_else(invalid)? as u8;
+                let value = hex(&digits[2..4]).ok_or_else(invalid)? as u8;
+                // the address is stored low byte first.
+                let address = hex(&digits[6..8]).ok_or_else(invalid)? << 8 | hex(&digits[4..6]).ok_or_else(invalid)?;
+                Ok(CheatCode::GameShark {
+                    kind,
+                    value,
+                    address: address as u16,
+                })
+            }
+            (6, true) | (9, true) => {
+                let d = |i: usize| hex(&digits[i..i + 1]).unwrap();
+                let value = (d(0) << 4 | d(1)) as u8;
+                let address = ((d(5) ^ 0xF) << 12 | d(2) << 8 | d(3) << 4 | d(4)) as u16;
+                if address > 0x7FFF {
+                    return Err(format!("{:?} does not point into ROM", code));
+                }
+                // the compare byte is scrambled : rotated left by two and XORed with 0xBA.
+                let compare = if digits.len() == 9 {
+                    let scrambled = (d(6) << 4 | d(8)) as u8;
+                    Some(scrambled.rotate_right(2) ^ 0xBA)
+                } else {
+                    None
+                };
+                Ok(CheatCode::GameGenie {
+                    address,
+                    value,
+                    compare,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub struct Cheat {
+    pub code: String,
+    pub name: String,
+    pub enabled: bool,
+    pub kind: CheatCode,
+}
+
+/// Cheats for the loaded ROM. Game Genie codes are looked up on every ROM read,
+/// GameShark codes are written once a frame by `MemoryBus::apply_cheats`.
+pub struct Cheats {
+    list: Vec<Cheat>,
+    // any enabled Game Genie code, so ROM reads stay cheap without them.
+    genie_active: bool,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats {
+            list: Vec::new(),
+            genie_active: false,
+        }
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.genie_active = false;
+    }
+
+    pub fn add(&mut self, code: &str, name: &str, enabled: bool) -> Result<(), String> {
+        let kind = CheatCode::parse(code)?;
+        self.list.push(Cheat {
+            code: code.to_ascii_uppercase(),
+            name: name.to_string(),
+            enabled,
+            kind,
+        });
+        self.refresh();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.list.len() {
+            return None;
+        }
+        let cheat = self.list.remove(index);
+        self.refresh();
+        Some(cheat)
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.list.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                self.refresh();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.genie_active = self
+            .list
+            .iter()
+            .any(|cheat| cheat.enabled && matches!(cheat.kind, CheatCode::GameGenie { .. }));
+    }
+
+    /// The byte a ROM read returns once Game Genie codes are applied.
+    pub fn patch_rom(&self, address: u16, value: u8) -> u8 {
+        if !self.genie_active {
+            return value;
+        }
+
+        for cheat in self.list.iter().filter(|cheat| cheat.enabled) {
+            if let CheatCode::GameGenie {
+                address: target,
+                value: new_value,
+                compare,
+            } = cheat.kind
+            {
+                if target == address && compare.is_none_or(|compare| compare == value) {
+                    return new_value;
+                }
+            }
+        }
+        value
+    }
+
+    /// Enabled GameShark patches as (kind, address, value).
+    pub fn ram_patches(&self) -> Vec<(u8, u16, u8)> {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.kind {
+                CheatCode::GameShark { kind, value, address } => Some((kind, address, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Codes of the cheats that are on, e.g. to record with a movie.
+    pub fn enabled_codes(&self) -> Vec<String> {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| cheat.code.clone())
+            .collect()
+    }
+
+    /// Reads a cheat file : one `on|off <code> [name]` a line, `;` starts a comment.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        self.clear();
+
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let enabled = match fields.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => {
+                    println!("{}:{} should start with on or off", path.display(), number + 1);
+                    continue;
+                }
+            };
+            let code = fields.next().unwrap_or("");
+            let name = fields.next().unwrap_or("").trim();
+            if let Err(error) = self.add(code, name, enabled) {
+                println!("{}:{} {}", path.display(), number + 1, error);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::from("; on|off <GameShark or Game Genie code> [name]\n");
+        for cheat in self.list.iter() {
+            let state = if cheat.enabled { "on" } else { "off" };
+            text.push_str(&format!("{} {} {}\n", state, cheat.code, cheat.name));
+        }
+        fs::write(path, text)
+    }
+}
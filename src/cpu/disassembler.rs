@@ -0,0 +1,489 @@
+use std::collections::BTreeMap;
+
+use super::instruction::Instruction;
+use super::target::*;
+
+// clock cycles per opcode. Conditional jumps, calls and returns list the cost when not taken.
+const CYCLES: [u8; 256] = [
+    4, 12, 8, 8, 4, 4, 8, 4, 20, 8, 8, 8, 4, 4, 8, 4, // 0x00
+    4, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4, // 0x10
+    8, 12, 8, 8, 4, 4, 8, 4, 8, 8, 8, 8, 4, 4, 8, 4, // 0x20
+    8, 12, 8, 8, 12, 12, 12, 4, 8, 8, 8, 8, 4, 4, 8, 4, // 0x30
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x40
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x50
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x60
+    8, 8, 8, 8, 8, 8, 4, 8, 4, 4, 4, 4, 4, 4, 8, 4, // 0x70
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x80
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x90
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0xA0
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0xB0
+    8, 12, 12, 16, 12, 16, 8, 16, 8, 16, 12, 4, 12, 24, 8, 16, // 0xC0
+    8, 12, 12, 0, 12, 16, 8, 16, 8, 16, 12, 0, 12, 0, 8, 16, // 0xD0
+    12, 12, 8, 0, 0, 16, 8, 16, 16, 4, 16, 0, 0, 0, 8, 16, // 0xE0
+    12, 12, 8, 4, 0, 16, 8, 16, 12, 8, 16, 4, 0, 0, 8, 16, // 0xF0
+];
+
+/// One decoded instruction and the bytes it was decoded from.
+pub struct Disassembly {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// Clock cycles, or the cycles when a conditional branch is not taken.
+    pub cycles: u8,
+    /// Clock cycles when a conditional branch is taken.
+    pub branch_cycles: Option<u8>,
+    /// Destination of a jump, call or restart.
+    pub target: Option<u16>,
+}
+
+impl Disassembly {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_call(&self) -> bool {
+        self.text.starts_with("CALL") || self.text.starts_with("RST")
+    }
+
+    pub fn cycles_text(&self) -> String {
+        match self.branch_cycles {
+            Some(taken) => format!("{}/{}", taken, self.cycles),
+            None => self.cycles.to_string(),
+        }
+    }
+}
+
+/// Decodes the instruction at `address`, fetching bytes through `read`.
+pub fn disassemble<F: FnMut(u16) -> u8>(address: u16, mut read: F) -> Disassembly {
+    let opcode = read(address);
+
+    if opcode == 0xCB {
+        let cb_opcode = read(address.wrapping_add(1));
+        let text = match Instruction::from_byte(cb_opcode, true) {
+            Some(instruction) => format_prefixed(&instruction),
+            None => format!("DB $CB,${:02X}", cb_opcode),
+        };
+        // (HL) operands go to memory, BIT only reads it back.
+        let cycles = match (cb_opcode & 0x07, cb_opcode >> 6) {
+            (6, 1) => 12,
+            (6, _) => 16,
+            _ => 8,
+        };
+        return Disassembly {
+            address,
+            bytes: vec![opcode, cb_opcode],
+            text,
+            cycles,
+            branch_cycles: None,
+            target: None,
+        };
+    }
+
+    let instruction = match Instruction::from_byte(opcode, false) {
+        Some(instruction) => instruction,
+        None => {
+            return Disassembly {
+                address,
+                bytes: vec![opcode],
+                text: format!("DB ${:02X}", opcode),
+                cycles: 0,
+                branch_cycles: None,
+                target: None,
+            }
+        }
+    };
+
+    let mut bytes = vec![opcode];
+    for i in 1..operand_length(&instruction) + 1 {
+        bytes.push(read(address.wrapping_add(i)));
+    }
+
+    let d8 = if bytes.len() > 1 { bytes[1] } else { 0 };
+    let d16 = if bytes.len() > 2 {
+        (bytes[2] as u16) << 8 | bytes[1] as u16
+    } else {
+        0
+    };
+    let text = format_instruction(&instruction, address, d8, d16);
+
+    let target = match &instruction {
+        Instruction::JP(JumpTest::HL) => None,
+        Instruction::JP(_) | Instruction::CALL(_) => Some(d16),
+        Instruction::JR(_) => Some(address.wrapping_add(2).wrapping_add(d8 as i8 as u16)),
+        Instruction::RST(_) => Some((opcode & 0x38) as u16),
+        _ => None,
+    };
+    let conditional = |test: &JumpTest| {
+        matches!(test, JumpTest::NotZero | JumpTest::Zero | JumpTest::NotCarry | JumpTest::Carry)
+    };
+    let branch_cycles = match &instruction {
+        Instruction::JR(test) if conditional(test) => Some(12),
+        Instruction::JP(test) if conditional(test) => Some(16),
+        Instruction::CALL(test) if conditional(test) => Some(24),
+        Instruction::RET(test) if conditional(test) => Some(20),
+        _ => None,
+    };
+
+    Disassembly {
+        address,
+        bytes,
+        text,
+        cycles: CYCLES[opcode as usize],
+        branch_cycles,
+        target,
+    }
+}
+
+/// Decodes every instruction from `start` up to and including `end`.
+pub fn disassemble_range<F: FnMut(u16) -> u8>(start: u16, end: u16, mut read: F) -> Vec<Disassembly> {
+    let mut lines = Vec::new();
+    let mut address = start as u32;
+
+    while address <= end as u32 {
+        let line = disassemble(address as u16, &mut read);
+        address += line.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+/// Names every jump, call and restart destination that falls inside the listing.
+pub fn jump_labels(lines: &[Disassembly]) -> BTreeMap<u16, String> {
+    let mut labels = BTreeMap::new();
+    let (first, last) = match (lines.first(), lines.last()) {
+        (Some(first), Some(last)) => (first.address, last.address),
+        _ => return labels,
+    };
+
+    for line in lines {
+        if let Some(target) = line.target {
+            if target < first || target > last {
+                continue;
+            }
+            let name = if line.is_call() {
+                format!("call_{:04X}", target)
+            } else {
+                format!("jump_{:04X}", target)
+            };
+            // a routine that is both called and jumped into reads better as a call.
+            let entry = labels.entry(target).or_insert_with(|| name.clone());
+            if line.is_call() {
+                *entry = name;
+            }
+        }
+    }
+    labels
+}
+
+/// Formats a listing with one instruction per line : address, bytes, text with
+/// destinations replaced by their labels, length and cycle count.
+pub fn format_listing(lines: &[Disassembly], labels: &BTreeMap<u16, String>) -> String {
+    let mut listing = String::new();
+
+    for line in lines {
+        if let Some(label) = labels.get(&line.address) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+
+        let mut text = line.text.clone();
+        if let Some(target) = line.target {
+            if let Some(label) = labels.get(&target) {
+                let operand = if line.text.starts_with("RST") {
+                    format!("${:02X}", target)
+                } else {
+                    format!("${:04X}", target)
+                };
+                text = text.replace(&operand, label);
+            }
+        }
+
+        let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        listing.push_str(&format!(
+            "    {:04X}: {:<9} {:<24} ; {} byte{}, {} cycles\n",
+            line.address,
+            bytes.join(" "),
+            text,
+            line.len(),
+            if line.len() == 1 { "" } else { "s" },
+            line.cycles_text()
+        ));
+    }
+    listing
+}
+
+/// Number of immediate bytes following the opcode.
+fn operand_length(instruction: &Instruction) -> u16 {
+    match instruction {
+        Instruction::ADD(ArthemeticTarget::D8)
+        | Instruction::ADD(ArthemeticTarget::SP)
+        | Instruction::SUB(ArthemeticTarget::D8)
+        | Instruction::AND(ArthemeticTarget::D8)
+        | Instruction::OR(ArthemeticTarget::D8)
+        | Instruction::ADC(ArthemeticTarget::D8)
+        | Instruction::SBC(ArthemeticTarget::D8)
+        | Instruction::XOR(ArthemeticTarget::D8)
+        | Instruction::CP(ArthemeticTarget::D8)
+        | Instruction::JR(_)
+        | Instruction::STOP
+        | Instruction::LD(LoadType::Byte(_, LoadByteSource::D8))
+        | Instruction::LD(LoadType::Byte(LoadByteTarget::OByte, _))
+        | Instruction::LD(LoadType::Byte(_, LoadByteSource::OByte))
+        | Instruction::LD2(LoadType::Word(_, LoadWordSource::SPr8)) => 1,
+
+        Instruction::JP(JumpTest::HL) => 0,
+        Instruction::JP(_)
+        | Instruction::CALL(_)
+        | Instruction::LD(LoadType::Byte(LoadByteTarget::OWord, _))
+        | Instruction::LD(LoadType::Byte(_, LoadByteSource::OWord))
+        | Instruction::LD2(LoadType::Word(_, LoadWordSource::D16))
+        | Instruction::LD2(LoadType::Word(LoadWordTarget::A16, _)) => 2,
+
+        _ => 0,
+    }
+}
+
+fn condition(test: &JumpTest) -> &'static str {
+    match test {
+        JumpTest::NotZero => "NZ,",
+        JumpTest::Zero => "Z,",
+        JumpTest::NotCarry => "NC,",
+        JumpTest::Carry => "C,",
+        _ => "",
+    }
+}
+
+fn arthemetic_operand(target: &ArthemeticTarget, d8: u8) -> String {
+    match target {
+        ArthemeticTarget::A => String::from("A"),
+        ArthemeticTarget::B => String::from("B"),
+        ArthemeticTarget::C => String::from("C"),
+        ArthemeticTarget::D => String::from("D"),
+        ArthemeticTarget::E => String::from("E"),
+        ArthemeticTarget::H => String::from("H"),
+        ArthemeticTarget::L => String::from("L"),
+        ArthemeticTarget::HL => String::from("(HL)"),
+        ArthemeticTarget::D8 => format!("${:02X}", d8),
+        ArthemeticTarget::HLBC => String::from("HL,BC"),
+        ArthemeticTarget::HLDE => String::from("HL,DE"),
+        ArthemeticTarget::HLHL => String::from("HL,HL"),
+        ArthemeticTarget::HLSP => String::from("HL,SP"),
+        ArthemeticTarget::SP => format!("SP,{}", d8 as i8),
+    }
+}
+
+fn inc_dec_operand(target: &IncDecTarget) -> &'static str {
+    match target {
+        IncDecTarget::BC => "BC",
+        IncDecTarget::DE => "DE",
+        IncDecTarget::HL => "HL",
+        IncDecTarget::SP => "SP",
+        IncDecTarget::A => "A",
+        IncDecTarget::B => "B",
+        IncDecTarget::C => "C",
+        IncDecTarget::D => "D",
+        IncDecTarget::E => "E",
+        IncDecTarget::H => "H",
+        IncDecTarget::L => "L",
+        IncDecTarget::HL2 => "(HL)",
+    }
+}
+
+fn stack_operand(target: &StackTarget) -> &'static str {
+    match target {
+        StackTarget::BC => "BC",
+        StackTarget::DE => "DE",
+        StackTarget::HL => "HL",
+        StackTarget::AF => "AF",
+    }
+}
+
+fn load_target(target: &LoadByteTarget, d8: u8, d16: u16) -> String {
+    match target {
+        LoadByteTarget::A => String::from("A"),
+        LoadByteTarget::B => String::from("B"),
+        LoadByteTarget::C => String::from("C"),
+        LoadByteTarget::D => String::from("D"),
+        LoadByteTarget::E => String::from("E"),
+        LoadByteTarget::H => String::from("H"),
+        LoadByteTarget::L => String::from("L"),
+        LoadByteTarget::HL => String::from("(HL)"),
+        LoadByteTarget::HLI => String::from("(HL+)"),
+        LoadByteTarget::HLD => String::from("(HL-)"),
+        LoadByteTarget::OC => String::from("($FF00+C)"),
+        LoadByteTarget::OWord => format!("(${:04X})", d16),
+        LoadByteTarget::OByte => format!("($FF{:02X})", d8),
+        LoadByteTarget::BCV => String::from("(BC)"),
+        LoadByteTarget::DEV => String::from("(DE)"),
+    }
+}
+
+fn load_source(source: &LoadByteSource, d8: u8, d16: u16) -> String {
+    match source {
+        LoadByteSource::A => String::from("A"),
+        LoadByteSource::B => String::from("B"),
+        LoadByteSource::C => String::from("C"),
+        LoadByteSource::D => String::from("D"),
+        LoadByteSource::E => String::from("E"),
+        LoadByteSource::H => String::from("H"),
+        LoadByteSource::L => String::from("L"),
+        LoadByteSource::D8 => format!("${:02X}", d8),
+        LoadByteSource::HL => String::from("(HL)"),
+        LoadByteSource::HLI => String::from("(HL+)"),
+        LoadByteSource::HLD => String::from("(HL-)"),
+        LoadByteSource::OC => String::from("($FF00+C)"),
+        LoadByteSource::OWord => format!("(${:04X})", d16),
+        LoadByteSource::OByte => format!("($FF{:02X})", d8),
+        LoadByteSource::BCV => String::from("(BC)"),
+        LoadByteSource::DEV => String::from("(DE)"),
+    }
+}
+
+fn format_instruction(instruction: &Instruction, address: u16, d8: u8, d16: u16) -> String {
+    match instruction {
+        Instruction::ADD(target) => match target {
+            ArthemeticTarget::HLBC
+            | ArthemeticTarget::HLDE
+            | ArthemeticTarget::HLHL
+            | ArthemeticTarget::HLSP
+            | ArthemeticTarget::SP => format!("ADD {}", arthemetic_operand(target, d8)),
+            _ => format!("ADD A,{}", arthemetic_operand(target, d8)),
+        },
+        Instruction::ADC(target) => format!("ADC A,{}", arthemetic_operand(target, d8)),
+        Instruction::SUB(target) => format!("SUB {}", arthemetic_operand(target, d8)),
+        Instruction::SBC(target) => format!("SBC A,{}", arthemetic_operand(target, d8)),
+        Instruction::AND(target) => format!("AND {}", arthemetic_operand(target, d8)),
+        Instruction::OR(target) => format!("OR {}", arthemetic_operand(target, d8)),
+        Instruction::XOR(target) => format!("XOR {}", arthemetic_operand(target, d8)),
+        Instruction::CP(target) => format!("CP {}", arthemetic_operand(target, d8)),
+
+        Instruction::INC(target) => format!("INC {}", inc_dec_operand(target)),
+        Instruction::DEC(target) => format!("DEC {}", inc_dec_operand(target)),
+
+        Instruction::JP(JumpTest::HL) => String::from("JP HL"),
+        Instruction::JP(test) => format!("JP {}${:04X}", condition(test), d16),
+        Instruction::JR(test) => {
+            let destination = address.wrapping_add(2).wrapping_add(d8 as i8 as u16);
+            format!("JR {}${:04X}", condition(test), destination)
+        }
+        Instruction::CALL(test) => format!("CALL {}${:04X}", condition(test), d16),
+        Instruction::RET(JumpTest::I) => String::from("RETI"),
+        Instruction::RET(JumpTest::Always) => String::from("RET"),
+        Instruction::RET(test) => format!("RET {}", condition(test).trim_end_matches(',')),
+        Instruction::RST(target) => {
+            let vector = match target {
+                RSTTarget::H00 => 0x00,
+                RSTTarget::H08 => 0x08,
+                RSTTarget::H10 => 0x10,
+                RSTTarget::H18 => 0x18,
+                RSTTarget::H20 => 0x20,
+                RSTTarget::H28 => 0x28,
+                RSTTarget::H30 => 0x30,
+                RSTTarget::H38 => 0x38,
+            };
+            format!("RST ${:02X}", vector)
+        }
+
+        Instruction::PUSH(target) => format!("PUSH {}", stack_operand(target)),
+        Instruction::POP(target) => format!("POP {}", stack_operand(target)),
+
+        Instruction::LD(LoadType::Byte(target, source)) => {
+            let mnemonic = match (target, source) {
+                (LoadByteTarget::OByte, _) | (_, LoadByteSource::OByte) => "LDH",
+                _ => "LD",
+            };
+            format!(
+                "{} {},{}",
+                mnemonic,
+                load_target(target, d8, d16),
+                load_source(source, d8, d16)
+            )
+        }
+        Instruction::LD2(LoadType::Word(target, source)) => {
+            let target = match target {
+                LoadWordTarget::BC => String::from("BC"),
+                LoadWordTarget::DE => String::from("DE"),
+                LoadWordTarget::HL => String::from("HL"),
+                LoadWordTarget::SP => String::from("SP"),
+                LoadWordTarget::A16 => format!("(${:04X})", d16),
+            };
+            let source = match source {
+                LoadWordSource::D16 => format!("${:04X}", d16),
+                LoadWordSource::SPr8 => format!("SP{:+}", d8 as i8),
+                LoadWordSource::HL => String::from("HL"),
+                LoadWordSource::SP => String::from("SP"),
+            };
+            format!("LD {},{}", target, source)
+        }
+
+        Instruction::NOP => String::from("NOP"),
+        Instruction::HALT => String::from("HALT"),
+        Instruction::STOP => String::from("STOP"),
+        Instruction::DI => String::from("DI"),
+        Instruction::EI => String::from("EI"),
+        Instruction::SCF => String::from("SCF"),
+        Instruction::CCF => String::from("CCF"),
+        Instruction::RRA => String::from("RRA"),
+        Instruction::RRCA => String::from("RRCA"),
+        Instruction::RLA => String::from("RLA"),
+        Instruction::RLCA => String::from("RLCA"),
+        Instruction::CPL => String::from("CPL"),
+        Instruction::DAA => String::from("DAA"),
+
+        _ => format!("{:?}", instruction),
+    }
+}
+
+fn prefix_operand(target: &PrefixTarget) -> &'static str {
+    match target {
+        PrefixTarget::B => "B",
+        PrefixTarget::C => "C",
+        PrefixTarget::D => "D",
+        PrefixTarget::E => "E",
+        PrefixTarget::H => "H",
+        PrefixTarget::L => "L",
+        PrefixTarget::HLV => "(HL)",
+        PrefixTarget::A => "A",
+    }
+}
+
+fn bit_operands(kind: &BitManipulationType) -> String {
+    let BitManipulationType::Bit(bit, source) = kind;
+    let bit = match bit {
+        TargetBit::B0 => 0,
+        TargetBit::B1 => 1,
+        TargetBit::B2 => 2,
+        TargetBit::B3 => 3,
+        TargetBit::B4 => 4,
+        TargetBit::B5 => 5,
+        TargetBit::B6 => 6,
+        TargetBit::B7 => 7,
+    };
+    let source = match source {
+        SourceRegister::A => "A",
+        SourceRegister::B => "B",
+        SourceRegister::C => "C",
+        SourceRegister::D => "D",
+        SourceRegister::E => "E",
+        SourceRegister::H => "H",
+        SourceRegister::L => "L",
+        SourceRegister::HLV => "(HL)",
+    };
+    format!("{},{}", bit, source)
+}
+
+fn format_prefixed(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::RLC(target) => format!("RLC {}", prefix_operand(target)),
+        Instruction::RRC(target) => format!("RRC {}", prefix_operand(target)),
+        Instruction::RL(target) => format!("RL {}", prefix_operand(target)),
+        Instruction::RR(target) => format!("RR {}", prefix_operand(target)),
+        Instruction::SLA(target) => format!("SLA {}", prefix_operand(target)),
+        Instruction::SRA(target) => format!("SRA {}", prefix_operand(target)),
+        Instruction::SWAP(target) => format!("SWAP {}", prefix_operand(target)),
+        Instruction::SRL(target) => format!("SRL {}", prefix_operand(target)),
+        Instruction::BIT(kind) => format!("BIT {}", bit_operands(kind)),
+        Instruction::RES(kind) => format!("RES {}", bit_operands(kind)),
+        Instruction::SET(kind) => format!("SET {}", bit_operands(kind)),
+        _ => format!("{:?}", instruction),
+    }
+}
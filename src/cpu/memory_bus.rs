@@ -4,6 +4,11 @@ use std::io;
 use std::io::prelude::*;
 
 use super::memory_map::*;
+use super::cheats::Cheats;
+use super::serial::Serial;
+use super::state::{StateReader, StateWriter};
+use super::timer::Timer;
+use super::watchpoint::Watchpoints;
 use crate::gpu::*;
 
 use crate::useful_func::*;
@@ -50,11 +55,24 @@ pub struct MemoryBus {
     pub gpu: GPU,
     pub interupt_master: bool,
 
-    pub mem_timer_counter: i32,
-    pub divider_register: u8,
+    pub timer: Timer,
+    // cycles into the running instruction, 4 for every access it made so far, and
+    // how many of those the timer was already clocked for.
+    instruction_cycles: u32,
+    timer_cycles: u32,
+    // CGB double speed mode, switched by STOP with KEY1 bit 0 set.
+    pub double_speed: bool,
 
     pub scan_line_counter: i16,
     pub joypad_state: u8,
+
+    pub watch: Watchpoints,
+    pub cheats: Cheats,
+    pub serial: Serial,
+
+    // 64 KB of plain RAM with every CPU access logged, for single-step tests.
+    flat_ram: bool,
+    pub access_log: Vec<(u16, u8, bool)>,
 }
 
 impl fmt::Debug for MemoryBus {
@@ -121,24 +139,36 @@ impl MemoryBus {
             gpu: GPU::new(),
             interupt_master: false,
 
-            mem_timer_counter: 0,
-            divider_register: 0,
+            timer: Timer::new(),
+            instruction_cycles: 0,
+            timer_cycles: 0,
+            double_speed: false,
 
             scan_line_counter: 456,
             joypad_state: 0,
+
+            watch: Watchpoints::new(),
+            cheats: Cheats::new(),
+            serial: Serial::new(),
+
+            flat_ram: false,
+            access_log: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) -> bool {
         self.gpu.reset();
         self.joypad_state = 0xFF;
-        self.divider_register = 0;
+        self.timer.reset(&mut self.memory);
+        self.double_speed = false;
+        self.memory[0xFF4D] = 0;
         self._current_ram_bank = 0;
 
         self.memory[0xFF00] = 0xFF;
+        self.memory[0xFF02] = 0x7E;
         self.memory[0xFF05] = 0x00;
         self.memory[0xFF06] = 0x00;
-        self.memory[0xFF07] = 0x00;
+        self.memory[0xFF07] = 0xF8;
         self.memory[0xFF10] = 0x80;
         self.memory[0xFF11] = 0xBF;
         self.memory[0xFF12] = 0xF3;
@@ -199,6 +229,8 @@ impl MemoryBus {
 
         self.memory = [0; 0x10000];
         self._cartridge = vec![0; MAX_CATRIDGE_SIZE];
+        self._ram_banks = [0; 0x8000];
+        self.serial.reset();
 
         let mut file = File::open(rom_path)?;
         println!("read file size = {:?}", file.read(&mut self._cartridge));
@@ -206,6 +238,7 @@ impl MemoryBus {
         (self.memory[0..0x8001]).copy_from_slice(&self._cartridge[0..0x8001]);
         (self._first_rom[0..256]).copy_from_slice(&self.memory[0..256]);
         (self.memory[0..256]).copy_from_slice(&self._bios);
+        self.gpu.rebuild_tile_set(&self.memory[VRAM_BEGIN..=VRAM_END]);
 
         self.memory[0xFF41] = 1;
         self.memory[0xFF43] = 0;
@@ -223,15 +256,15 @@ impl MemoryBus {
         let mut background_memory: u16 = 0;
         let mut unsig = true;
 
-        let scroll_Y = self.read_byte(0xFF42);
-        let scroll_X = self.read_byte(0xFF43);
-        let window_Y = self.read_byte(0xFF4A);
-        let window_X = self.read_byte(0xFF4B).wrapping_sub(7);
+        let scroll_Y = self.peek(0xFF42);
+        let scroll_X = self.peek(0xFF43);
+        let window_Y = self.peek(0xFF4A);
+        let window_X = self.peek(0xFF4B).wrapping_sub(7);
 
         let mut using_window = false;
 
         if test_bit(control, 5) {
-            if window_Y <= self.read_byte(0xFF44) {
+            if window_Y <= self.peek(0xFF44) {
                 using_window = true;
             }
         }
@@ -260,9 +293,9 @@ impl MemoryBus {
         let mut yPos: u8 = 0;
 
         if !using_window {
-            yPos = scroll_Y.wrapping_add(self.read_byte(0xFF44));
+            yPos = scroll_Y.wrapping_add(self.peek(0xFF44));
         } else {
-            yPos = self.read_byte(0xFF44).wrapping_sub(window_Y);
+            yPos = self.peek(0xFF44).wrapping_sub(window_Y);
         }
 
         let tile_row: u16 = (yPos.wrapping_div(8) as u16).wrapping_mul(32);
@@ -282,9 +315,9 @@ impl MemoryBus {
             let tile_address: u16 = background_memory + tile_row + tile_col;
 
             if unsig {
-                tile_num = (self.read_byte(tile_address) as u8) as i16;
+                tile_num = (self.peek(tile_address) as u8) as i16;
             } else {
-                tile_num = (self.read_byte(tile_address) as i8) as i16;
+                tile_num = (self.peek(tile_address) as i8) as i16;
             }
 
             let mut tile_location: u16 = tile_data;
@@ -297,8 +330,8 @@ impl MemoryBus {
 
             let mut line: u8 = yPos % 8;
             line *= 2;
-            let data_1 = self.read_byte(tile_location + line as u16);
-            let data_2 = self.read_byte(tile_location + line as u16 + 1);
+            let data_1 = self.peek(tile_location + line as u16);
+            let data_2 = self.peek(tile_location + line as u16 + 1);
 
             let mut color_bit = x_pos % 8;
             color_bit = color_bit.wrapping_sub(7);
@@ -310,26 +343,7 @@ impl MemoryBus {
 
             let col = self.get_color(color_num, 0xFF47);
 
-            let mut red = 0;
-            let mut green = 0;
-            let mut blue = 0;
-
-            match col {
-                Color::LightestGreen => {
-                    red = 155 ; green = 188 ; blue = 15;
-                }
-                Color::LightGreen => {
-                    red = 139 ; green = 172 ; blue = 15;
-                }
-                Color::DarkGreen => {
-                    red = 48 ; green = 98 ; blue = 48;
-                }
-                _ => {
-                    red = 15 ; green = 56 ; blue = 15;
-                }
-            }
-
-            let finaly: u16 = self.read_byte(0xFF44) as u16;
+            let finaly: u16 = self.peek(0xFF44) as u16;
 
             if (finaly < 0) || (finaly > 143) || (pixel < 0) || (pixel > 159) {
                 continue;
@@ -337,7 +351,7 @@ impl MemoryBus {
 
             // ADD RGB INTO SCREEN BUFFER
             let index: u16 = 160 * finaly + pixel as u16;
-            self.gpu.buffer[index as usize] = from_u8_rgb(red,green,blue);
+            self.gpu.buffer[index as usize] = self.gpu.palette[col as usize];
         }
     }
 
@@ -349,16 +363,16 @@ impl MemoryBus {
 
         for sprite in 0u8..40 {
             let index = sprite.wrapping_mul(4);
-            let y_pos = self.read_byte(0xFE00 + index as u16).wrapping_sub(16);
-            let x_pos = self.read_byte(0xFE00 + index as u16 + 1).wrapping_sub(8);
+            let y_pos = self.peek(0xFE00 + index as u16).wrapping_sub(16);
+            let x_pos = self.peek(0xFE00 + index as u16 + 1).wrapping_sub(8);
             let tile_location =
-                self.read_byte(0xFE00u16.wrapping_add(index as u16).wrapping_add(2));
-            let attributes = self.read_byte(0xFE00u16.wrapping_add(index as u16).wrapping_add(3));
+                self.peek(0xFE00u16.wrapping_add(index as u16).wrapping_add(2));
+            let attributes = self.peek(0xFE00u16.wrapping_add(index as u16).wrapping_add(3));
 
             let y_flip = test_bit(attributes, 6);
             let x_flip = test_bit(attributes, 5);
 
-            let scan_line = self.read_byte(0xFF44);
+            let scan_line = self.peek(0xFF44);
 
             let ysize: u8 = if use8x16 { 16 } else { 8 };
 
@@ -377,8 +391,8 @@ impl MemoryBus {
                     + (((tile_location as u16) & 0x00FF).wrapping_mul(16))
                     + (line as u16 & 0x00FF);
 
-                let data_1 = self.read_byte(data_address);
-                let data_2 = self.read_byte(data_address + 1);
+                let data_1 = self.peek(data_address);
+                let data_2 = self.peek(data_address + 1);
 
                 for tile_pixel in (0..8).rev() {
                     let mut color_bit: i32 = tile_pixel;
@@ -412,24 +426,6 @@ impl MemoryBus {
                     if col == Color::LightestGreen {
                         continue;
                     }
-                    let mut red: u8 = 0;
-                    let mut green: u8 = 0;
-                    let mut blue: u8 = 0;
-
-                    match col {
-                        Color::LightestGreen => {
-                            red = 155 ; green = 188 ; blue = 15;
-                        }
-                        Color::LightGreen => {
-                            red = 139 ; green = 172 ; blue = 15;
-                        }
-                        Color::DarkGreen => {
-                            red = 48 ; green = 98 ; blue = 48;
-                        }
-                        _ => {
-                            red = 15 ; green = 56 ; blue = 15;
-                        }
-                    }
 
                     let mut x_pix = 0 - tile_pixel;
                     x_pix += 7;
@@ -442,7 +438,7 @@ impl MemoryBus {
 
                     let index = 160 * scan_line as i32 + pixel;
 
-                    self.gpu.buffer[index as usize] = from_u8_rgb(red,green,blue);
+                    self.gpu.buffer[index as usize] = self.gpu.palette[col as usize];
                 }
             }
         }
@@ -450,7 +446,7 @@ impl MemoryBus {
 
     pub fn get_color(&mut self, color_num: u8, address: u16) -> Color {
         let mut res = Color::LightestGreen;
-        let palette = self.read_byte(address);
+        let palette = self.peek(address);
         let mut hi = 0;
         let mut lo = 0;
 
@@ -574,15 +570,64 @@ impl MemoryBus {
         }
     }
 
+    /// Drops the memory map : no ROM, banking or IO, just RAM. For CPU tests.
+    pub fn set_flat_ram(&mut self, flat: bool) {
+        self.flat_ram = flat;
+        self.access_log.clear();
+    }
+
+    /// Called as the CPU starts an instruction, before its opcode is read.
+    pub fn start_instruction(&mut self) {
+        self.instruction_cycles = 0;
+        self.timer_cycles = 0;
+    }
+
+    /// Clocks the timer up to `cycles` into the running instruction, for the part
+    /// not clocked yet. A timer overflow requests its interupt.
+    pub fn update_timer(&mut self, cycles: u32) {
+        if cycles > self.timer_cycles {
+            if self.timer.update(&mut self.memory, cycles - self.timer_cycles) {
+                self.memory[0xFF0F] |= 1 << 2;
+            }
+            self.timer_cycles = cycles;
+        }
+    }
+
+    /// Each access is one M-cycle, after the ones before it. The timer registers
+    /// and IF see the timer as it is on that cycle rather than at the end of the
+    /// instruction. Internal cycles, like the one before PUSH writes, aren't counted.
+    fn access(&mut self, address: u16) {
+        if !self.flat_ram && matches!(address, 0xFF04..=0xFF07 | 0xFF0F) {
+            self.update_timer(self.instruction_cycles);
+        }
+        self.instruction_cycles += 4;
+    }
+
     pub fn read_byte(&mut self, address: u16) -> u8 {
+        self.access(address);
+        let value = self.peek(address);
+        self.watch.check(address, value, false);
+        if self.flat_ram {
+            self.access_log.push((address, value, false));
+        }
+        value
+    }
+
+    /// Reads like the CPU would, without tripping watchpoints.
+    pub fn peek(&mut self, address: u16) -> u8 {
+        if self.flat_ram {
+            return self.memory[address as usize];
+        }
+
         let address = address as usize;
         match address {
             ROM_0_BEGIN..=ROM_0_END => {
-                return self.memory[address];
+                return self.cheats.patch_rom(address as u16, self.memory[address]);
             }
 
             ROM_1_BEGIN..=ROM_1_END => {
-                return self._cartridge[address + (self._current_rom_bank - 1) as usize * 0x4000];
+                let value = self._cartridge[address + (self._current_rom_bank - 1) as usize * 0x4000];
+                return self.cheats.patch_rom(address as u16, value);
             }
 
             VRAM_BEGIN..=VRAM_END => {
@@ -607,6 +652,19 @@ impl MemoryBus {
                 return self.get_joypad_state();
             }
 
+            // only 5 interrupt bits, the rest read as 1.
+            0xFF0F => {
+                return self.memory[address] | 0xE0;
+            }
+
+            // KEY1, the current speed in bit 7 and a switch armed in bit 0.
+            0xFF4D if self.is_cgb_catridge() => {
+                return (self.double_speed as u8) << 7 | 0x7E | self.memory[address] & 0x01;
+            }
+            0xFF4D => {
+                return 0xFF;
+            }
+
             0xFFFF => {
                 return self.memory[0xFFFF];
             }
@@ -619,11 +677,29 @@ impl MemoryBus {
                 return self.memory[address];
             }
 
-            _ => panic!("Reading UnAccesed Memory {:X}", address),
+            // 0xFEA0 - 0xFEFF, nothing answers there.
+            _ => {
+                return 0xFF;
+            }
         }
     }
 
     pub fn write_bytes(&mut self, address: u16, value: u8) {
+        self.access(address);
+        self.watch.check(address, value, true);
+        if self.flat_ram {
+            self.access_log.push((address, value, true));
+        }
+        self.poke(address, value);
+    }
+
+    /// Writes like the CPU would, without tripping watchpoints.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if self.flat_ram {
+            self.memory[address as usize] = value;
+            return;
+        }
+
         let address = address as usize;
         match address {
             ROM_0_BEGIN..=ROM_1_END => {
@@ -633,6 +709,7 @@ impl MemoryBus {
 
             VRAM_BEGIN..=VRAM_END => {
                 self.memory[address] = value;
+                self.gpu.write_vram(&self.memory[VRAM_BEGIN..=VRAM_END], address - VRAM_BEGIN);
             }
 
             EXTERNAL_RAM_BEGIN..=EXTERNAL_RAM_END => {
@@ -656,19 +733,11 @@ impl MemoryBus {
             }
 
             //Divider Register
-            0xFF04 => {
-                self.memory[address] = 0;
-            }
-
-            0xFF07 => {
-                let current_freq = self.get_clock_freq();
-                self.memory[address] = 0xF8 | value;
-                let new_freq = self.get_clock_freq();
-
-                if current_freq != new_freq {
-                    self.set_clock_freq();
-                }
-            }
+            0xFF04 => self.timer.write_divider(&mut self.memory),
+            0xFF05 => self.timer.write_counter(&mut self.memory, value),
+            0xFF06 => self.timer.write_modulo(&mut self.memory, value),
+            0xFF07 => self.timer.write_control(&mut self.memory, value),
+            0xFF4D => self.memory[address] = value & 0x01,
 
             0xFF40 => {
                 self.memory[address] = value;
@@ -686,6 +755,11 @@ impl MemoryBus {
                 self.do_dma_transfer(value);
             }
 
+            // Serial transfer control
+            0xFF02 => {
+                self.serial.write_control(&mut self.memory, value);
+            }
+
             0xFF50 => {
                 println!("Removing bios");
                 for i in 0..256 {
@@ -715,19 +789,8 @@ impl MemoryBus {
         let address = (value as u16) << 8;
 
         for i in 0..0xA0 {
-            let v = self.read_byte(address + i);
-            self.write_bytes(0xFE00 + i, v);
-        }
-    }
-
-    pub fn set_clock_freq(&mut self) {
-        let freq = self.get_clock_freq();
-        match freq {
-            0 => self.mem_timer_counter = 1024, // freq 4096
-            1 => self.mem_timer_counter = 16,   // freq 262144
-            2 => self.mem_timer_counter = 64,   // freq 65536
-            3 => self.mem_timer_counter = 256,  // freq 16382
-            _ => panic!("Unhandled set_freq arm"),
+            let v = self.peek(address + i);
+            self.poke(0xFE00 + i, v);
         }
     }
 
@@ -784,26 +847,123 @@ impl MemoryBus {
         res
     }
 
-    pub fn clock_enabled(&mut self) -> bool {
-        if test_bit(self.read_byte(0xff07), 2) {
-            true
-        } else {
-            false
+    /// The header says the game can use CGB features.
+    pub fn is_cgb_catridge(&self) -> bool {
+        self._cartridge[0x143] & 0x80 != 0
+    }
+
+    /// Called by STOP. True if a speed switch was armed and has happened.
+    pub fn speed_switch(&mut self) -> bool {
+        if !self.is_cgb_catridge() || self.memory[0xFF4D] & 0x01 == 0 {
+            return false;
         }
+        self.memory[0xFF4D] = 0;
+        self.double_speed = !self.double_speed;
+        true
     }
 
-    pub fn do_divider_register(&mut self, cycles: u32) {
-        let (new, overflowed) = self.divider_register.overflowing_add(cycles as u8);
+    /// ROM size from the cartridge header, 32 KB shifted left by the byte at 0x148.
+    pub fn rom_size(&self) -> usize {
+        let shift = self._cartridge[0x148].min(6);
+        0x8000 << shift
+    }
 
-        if overflowed {
-            self.divider_register = new;
-            self.memory[0xff04] = self.memory[0xff04].wrapping_add(1);
-        } else {
-            self.divider_register = new;
+    pub fn rom_bank_count(&self) -> usize {
+        self.rom_size() / 0x4000
+    }
+
+    /// Cartridge RAM banks from the header byte at 0x149, at most the 4 kept here.
+    pub fn ram_bank_count(&self) -> usize {
+        if self._mbc2 {
+            return 1;
+        }
+        match self._cartridge[0x149] {
+            0 => 0,
+            1 | 2 => 1,
+            _ => 4,
+        }
+    }
+
+    pub fn current_rom_bank(&self) -> u8 {
+        self._current_rom_bank
+    }
+
+    pub fn current_ram_bank(&self) -> u8 {
+        self._current_ram_bank
+    }
+
+    /// 16 KB of cartridge ROM, whether or not the bank is switched in.
+    pub fn rom_bank(&self, bank: usize) -> Option<&[u8]> {
+        self._cartridge.get(bank * 0x4000..(bank + 1) * 0x4000)
+    }
+
+    /// 8 KB of cartridge RAM, whether or not the bank is switched in.
+    pub fn ram_bank(&self, bank: usize) -> Option<&[u8]> {
+        self._ram_banks.get(bank * 0x2000..(bank + 1) * 0x2000)
+    }
+
+    /// Rewrites the GameShark patches, done once a frame like the real device.
+    pub fn apply_cheats(&mut self) {
+        for (kind, address, value) in self.cheats.ram_patches() {
+            match (kind, address) {
+                (0x80..=0x83, 0xA000..=0xBFFF) => {
+                    let bank = (kind & 0x03) as usize;
+                    self._ram_banks[bank * 0x2000 + address as usize - 0xA000] = value;
+                }
+                // patching ROM would switch banks instead.
+                (_, 0x0000..=0x7FFF) => {}
+                _ => self.poke(address, value),
+            }
         }
     }
 
-    pub fn get_clock_freq(&mut self) -> u8 {
-        self.read_byte(0xFF07) & 0x3
+    /// CRC-32 of the loaded catridge, used to tie movies and states to a ROM.
+    pub fn rom_checksum(&self) -> u32 {
+        crc32(&self._cartridge)
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self._inbios);
+        w.bool(self._gameLoaded);
+        w.bool(self._mbc1);
+        w.bool(self._mbc2);
+        w.u8(self._current_rom_bank);
+        w.bytes(&self._ram_banks);
+        w.u8(self._current_ram_bank);
+        w.bool(self._enable_ram);
+        w.u8(self._ram_mode);
+        w.bool(self._rom_banking);
+        w.bytes(&self._first_rom);
+        w.bytes(&self.memory);
+        w.bool(self.interupt_master);
+        self.timer.save_state(w);
+        self.serial.save_state(w);
+        w.bool(self.double_speed);
+        w.i16(self.scan_line_counter);
+        w.u8(self.joypad_state);
+        self.gpu.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self._inbios = r.bool()?;
+        self._gameLoaded = r.bool()?;
+        self._mbc1 = r.bool()?;
+        self._mbc2 = r.bool()?;
+        self._current_rom_bank = r.u8()?;
+        r.bytes(&mut self._ram_banks)?;
+        self._current_ram_bank = r.u8()?;
+        self._enable_ram = r.bool()?;
+        self._ram_mode = r.u8()?;
+        self._rom_banking = r.bool()?;
+        r.bytes(&mut self._first_rom)?;
+        r.bytes(&mut self.memory)?;
+        self.interupt_master = r.bool()?;
+        self.timer.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.double_speed = r.bool()?;
+        self.scan_line_counter = r.i16()?;
+        self.joypad_state = r.u8()?;
+        self.gpu.rebuild_tile_set(&self.memory[VRAM_BEGIN..=VRAM_END]);
+        self.gpu.load_state(r)
     }
 }
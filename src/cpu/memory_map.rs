@@ -62,3 +62,25 @@ pub const ZRAM_SIZE: usize = ZRAM_END - ZRAM_BEGIN + 1;
 
 // INTERUPT ENABLE REGISTER
 pub const INTERUPT_REG: usize = 0xFFFF;
+
+// Named regions, as the memory viewer and dumps refer to them.
+pub const REGIONS: [(&str, usize, usize); 10] = [
+    ("ROM0", ROM_0_BEGIN, ROM_0_END),
+    ("ROMX", ROM_1_BEGIN, ROM_1_END),
+    ("VRAM", VRAM_BEGIN, VRAM_END),
+    ("SRAM", EXTERNAL_RAM_BEGIN, EXTERNAL_RAM_END),
+    ("WRAM", WORKING_RAM_BEGIN, WORKING_RAM_END),
+    ("ECHO", W_SHADOW_RAM_BEGIN, W_SHADOW_RAM_END),
+    ("OAM", SPRITE_RAM_BEGIN, SPRITE_RAM_END),
+    ("IO", MM_IO_BEGIN, MM_IO_END),
+    ("HRAM", ZRAM_BEGIN, ZRAM_END),
+    ("IE", INTERUPT_REG, INTERUPT_REG),
+];
+
+/// Name of the region `address` falls in, or None for the unusable 0xFEA0-0xFEFF.
+pub fn region_name(address: usize) -> Option<&'static str> {
+    REGIONS
+        .iter()
+        .find(|(_, begin, end)| (*begin..=*end).contains(&address))
+        .map(|(name, _, _)| *name)
+}
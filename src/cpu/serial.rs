@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+
+use super::state::{StateReader, StateWriter};
+
+pub const SB: usize = 0xFF01;
+pub const SC: usize = 0xFF02;
+
+// 8192 bits a second with the internal clock, 8 bits a transfer.
+const CYCLES_PER_BIT: i32 = 4194304 / 8192;
+const CYCLES_PER_TRANSFER: i32 = CYCLES_PER_BIT * 8;
+
+/// Whatever is plugged into the link port.
+pub trait SerialDevice {
+    /// Our clock shifted `sent` out, returns the byte shifted in.
+    fn exchange(&mut self, sent: u8) -> u8;
+
+    /// Asked about once a bit time whenever our clock isn't running a transfer, so
+    /// the other side can clock one. `sent` is SB while the game waits on the
+    /// external clock, None otherwise. Returns the byte received, if any.
+    fn poll_external(&mut self, _sent: Option<u8>) -> Option<u8> {
+        None
+    }
+}
+
+/// Nothing plugged in : the line floats high and nobody ever drives the clock.
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn exchange(&mut self, _sent: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// Prints what the game sends as text, which is how test ROMs report results.
+pub struct StdoutDevice;
+
+impl SerialDevice for StdoutDevice {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        out.write_all(&[sent]).ok();
+        if sent == b'\n' {
+            out.flush().ok();
+        }
+        0xFF
+    }
+}
+
+/// The link port : SB and SC live in memory, this does the transfers.
+pub struct Serial {
+    device: Box<dyn SerialDevice>,
+    // cycles until the running internal clock transfer is done, 0 before it starts.
+    cycles_left: i32,
+    // cycles since the device was last polled for the external clock.
+    poll_cycles: i32,
+    // keeps every byte sent when set, for the test harnesses.
+    pub capture: bool,
+    pub output: Vec<u8>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            device: Box::new(Disconnected),
+            cycles_left: 0,
+            poll_cycles: 0,
+            capture: false,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn reset(&mut self) {
+        self.cycles_left = 0;
+        self.poll_cycles = 0;
+        self.output.clear();
+    }
+
+    /// A write to SC. With bit 7 and the internal clock (bit 0) set, the transfer
+    /// starts now; with the external clock it waits for the other side.
+    pub fn write_control(&mut self, memory: &mut [u8], value: u8) {
+        // the unused bits read as 1.
+        memory[SC] = value | 0x7E;
+        self.cycles_left = 0;
+    }
+
+    /// Moves a transfer on by `cycles`. True when one has completed, which
+    /// requests the serial interrupt.
+    pub fn update(&mut self, memory: &mut [u8], cycles: u32) -> bool {
+        let sent = memory[SB];
+        let received = if memory[SC] & 0x81 == 0x81 {
+            if self.cycles_left == 0 {
+                self.cycles_left = CYCLES_PER_TRANSFER;
+            }
+            self.cycles_left -= cycles as i32;
+            if self.cycles_left > 0 {
+                return false;
+            }
+            self.cycles_left = 0;
+            self.device.exchange(sent)
+        } else {
+            // the other side's clock can't tick faster than ours does.
+            self.poll_cycles += cycles as i32;
+            if self.poll_cycles < CYCLES_PER_BIT {
+                return false;
+            }
+            self.poll_cycles = 0;
+
+            let waiting = memory[SC] & 0x80 != 0;
+            match self.device.poll_external(if waiting { Some(sent) } else { None }) {
+                Some(received) if waiting => received,
+                _ => return false,
+            }
+        };
+
+        if self.capture {
+            self.output.push(sent);
+        }
+        memory[SB] = received;
+        memory[SC] &= 0x7F;
+        true
+    }
+
+    /// Where the running transfer and the next poll are, so a loaded state
+    /// finishes them on the same cycle. The device and the capture aren't state.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.i32(self.cycles_left);
+        w.i32(self.poll_cycles);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.cycles_left = r.i32()?;
+        self.poll_cycles = r.i32()?;
+        Ok(())
+    }
+}
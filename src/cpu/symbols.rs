@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Labels from an RGBDS `.sym` file, lines of `BB:AAAA Name` where BB is the bank.
+#[derive(Debug)]
+pub struct Symbols {
+    by_address: BTreeMap<(u16, u16), String>,
+    by_name: HashMap<String, (u16, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            by_address: BTreeMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_address.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.by_address.clear();
+        self.by_name.clear();
+    }
+
+    pub fn add(&mut self, bank: u16, address: u16, name: &str) {
+        self.by_address.entry((bank, address)).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), (bank, address));
+    }
+
+    /// Replaces the symbols with the ones in `path`. Lines that don't parse are skipped.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        self.clear();
+
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let mut fields = line.split_whitespace();
+            let (location, name) = match (fields.next(), fields.next()) {
+                (Some(location), Some(name)) => (location, name),
+                _ => continue,
+            };
+
+            let parsed = location.split_once(':').and_then(|(bank, address)| {
+                let bank = u16::from_str_radix(bank, 16).ok()?;
+                let address = u16::from_str_radix(address, 16).ok()?;
+                Some((bank, address))
+            });
+            if let Some((bank, address)) = parsed {
+                self.add(bank, address, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// `.sym` files name ROM0 and WRAM0 as bank 0, the switchable areas by their
+    /// bank. WRAMX is always bank 1 on the DMG.
+    fn bank_at(address: u16, rom_bank: u16, ram_bank: u16) -> u16 {
+        match address {
+            0x4000..=0x7FFF => rom_bank,
+            0xA000..=0xBFFF => ram_bank,
+            0xD000..=0xDFFF => 1,
+            _ => 0,
+        }
+    }
+
+    /// The label right at `address`, with the ROM and RAM banks currently switched in.
+    pub fn label(&self, address: u16, rom_bank: u16, ram_bank: u16) -> Option<&str> {
+        self.by_address
+            .get(&(Symbols::bank_at(address, rom_bank, ram_bank), address))
+            .map(|name| name.as_str())
+    }
+
+    /// `label` or `label+offset` from the closest label at or before `address`
+    /// in the same bank, within 0x100 bytes.
+    pub fn locate(&self, address: u16, rom_bank: u16, ram_bank: u16) -> Option<String> {
+        let bank = Symbols::bank_at(address, rom_bank, ram_bank);
+        let ((found_bank, start), name) = self.by_address.range(..=(bank, address)).next_back()?;
+        // the closest one can be in a lower bank, at a higher address.
+        if *found_bank != bank {
+            return None;
+        }
+        let offset = address - start;
+        if offset >= 0x100 {
+            return None;
+        }
+
+        if offset == 0 {
+            Some(name.clone())
+        } else {
+            Some(format!("{}+{:X}", name, offset))
+        }
+    }
+
+    /// Bank and address of a label.
+    pub fn address_of(&self, name: &str) -> Option<(u16, u16)> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Labels in one bank, e.g. to name the lines of a listing.
+    pub fn in_bank(&self, bank: u16) -> BTreeMap<u16, String> {
+        self.by_address
+            .range((bank, 0)..=(bank, 0xFFFF))
+            .map(|((_, address), name)| (*address, name.clone()))
+            .collect()
+    }
+}
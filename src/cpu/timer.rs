@@ -1,5 +1,120 @@
+use std::io;
+
+use super::state::{StateReader, StateWriter};
+
+pub const DIV: usize = 0xFF04;
 pub const TIMA: usize = 0xFF05;
 pub const TMA: usize = 0xFF06;
 pub const TMC: usize = 0xFF07;
 
-pub const CLOCK_CYCLES: usize = 4194304;
+// the divider bit TIMA counts the falling edges of, for each clock select.
+const TIMER_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+/// DIV, TIMA, TMA and TAC live in memory, this does the counting. Everything hangs
+/// off a 16-bit divider going up every cycle, DIV is its top byte. TIMA goes up
+/// when the divider bit picked by TAC, ANDed with the enable bit, goes from 1 to
+/// 0, which is why writing DIV or TAC can bump it.
+pub struct Timer {
+    divider: u16,
+    // TIMA overflowed last M-cycle and reads 0, TMA gets loaded on this one.
+    overflowed: bool,
+    // TMA was loaded this M-cycle, a TIMA write now is lost and a TMA write goes through.
+    reloaded: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            divider: 0,
+            overflowed: false,
+            reloaded: false,
+        }
+    }
+
+    pub fn reset(&mut self, memory: &mut [u8]) {
+        *self = Timer::new();
+        memory[DIV] = 0;
+    }
+
+    fn input(&self, memory: &[u8]) -> bool {
+        let control = memory[TMC];
+        control & 0x04 != 0 && self.divider & TIMER_BITS[(control & 0x03) as usize] != 0
+    }
+
+    fn increment(&mut self, memory: &mut [u8]) {
+        let (value, overflowed) = memory[TIMA].overflowing_add(1);
+        memory[TIMA] = value;
+        self.overflowed = overflowed;
+    }
+
+    /// Moves the timer on by `cycles`. True when TMA was reloaded, which requests
+    /// the timer interrupt.
+    pub fn update(&mut self, memory: &mut [u8], cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles / 4 {
+            self.reloaded = false;
+            if self.overflowed {
+                memory[TIMA] = memory[TMA];
+                self.overflowed = false;
+                self.reloaded = true;
+                interrupt = true;
+            }
+
+            let before = self.input(memory);
+            self.divider = self.divider.wrapping_add(4);
+            memory[DIV] = (self.divider >> 8) as u8;
+            if before && !self.input(memory) {
+                self.increment(memory);
+            }
+        }
+        interrupt
+    }
+
+    /// Any write to DIV clears the whole divider.
+    pub fn write_divider(&mut self, memory: &mut [u8]) {
+        let before = self.input(memory);
+        self.divider = 0;
+        memory[DIV] = 0;
+        if before {
+            self.increment(memory);
+        }
+    }
+
+    pub fn write_counter(&mut self, memory: &mut [u8], value: u8) {
+        if self.reloaded {
+            return;
+        }
+        // writing in the cycle after an overflow cancels the reload.
+        self.overflowed = false;
+        memory[TIMA] = value;
+    }
+
+    pub fn write_modulo(&mut self, memory: &mut [u8], value: u8) {
+        memory[TMA] = value;
+        if self.reloaded {
+            memory[TIMA] = value;
+        }
+    }
+
+    pub fn write_control(&mut self, memory: &mut [u8], value: u8) {
+        let before = self.input(memory);
+        // the unused bits read as 1.
+        memory[TMC] = value | 0xF8;
+        if before && !self.input(memory) {
+            self.increment(memory);
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.divider);
+        w.bool(self.overflowed);
+        w.bool(self.reloaded);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.divider = r.u16()?;
+        self.overflowed = r.bool()?;
+        self.reloaded = r.bool()?;
+        Ok(())
+    }
+}
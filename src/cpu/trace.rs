@@ -0,0 +1,115 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::registers::Registers;
+
+// lines buffered before the file is flushed, so an abrupt exit loses little.
+const FLUSH_INTERVAL: u64 = 4096;
+
+/// When tracing begins.
+#[derive(Clone, Copy)]
+pub enum TraceStart {
+    Immediately,
+    AfterInstructions(u64),
+    AtPc(u16),
+}
+
+/// Writes one line per executed instruction in the gameboy-doctor layout :
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+/// With `labels` set, lines at a symbol end in ` ; <label>`, which gameboy-doctor won't accept.
+pub struct Tracer {
+    pub labels: bool,
+    out: BufWriter<File>,
+    start: TraceStart,
+    started: bool,
+    executed: u64,
+    lines: u64,
+    max_lines: Option<u64>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("tracer")
+            .field("lines", &self.lines)
+            .finish()
+    }
+}
+
+impl Tracer {
+    pub fn create(path: &Path, start: TraceStart, max_lines: Option<u64>) -> io::Result<Tracer> {
+        Ok(Tracer {
+            labels: false,
+            out: BufWriter::new(File::create(path)?),
+            start,
+            started: false,
+            executed: 0,
+            lines: 0,
+            max_lines,
+        })
+    }
+
+    /// Called before every instruction, returns true if this one should be logged.
+    pub fn wants(&mut self, pc: u16) -> bool {
+        if !self.started {
+            self.started = match self.start {
+                TraceStart::Immediately => true,
+                TraceStart::AfterInstructions(count) => self.executed >= count,
+                TraceStart::AtPc(address) => pc == address,
+            };
+            self.executed += 1;
+        }
+        self.started && !self.is_finished()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.max_lines, Some(max) if self.lines >= max)
+    }
+
+    pub fn log(
+        &mut self,
+        registers: &Registers,
+        sp: u16,
+        pc: u16,
+        pcmem: [u8; 4],
+        label: Option<&str>,
+    ) -> io::Result<()> {
+        write!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.a,
+            registers.f.con(),
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+            sp,
+            pc,
+            pcmem[0],
+            pcmem[1],
+            pcmem[2],
+            pcmem[3]
+        )?;
+        match label {
+            Some(label) => writeln!(self.out, " ; {}", label)?,
+            None => writeln!(self.out)?,
+        }
+
+        self.lines += 1;
+        if self.lines.is_multiple_of(FLUSH_INTERVAL) || self.is_finished() {
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+}
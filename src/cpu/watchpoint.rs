@@ -0,0 +1,215 @@
+use std::fmt;
+
+use crate::useful_func::parse_hex;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    pub fn parse(text: &str) -> Option<Access> {
+        match text {
+            "r" => Some(Access::Read),
+            "w" => Some(Access::Write),
+            "rw" => Some(Access::ReadWrite),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Value,
+    Address,
+    Pc,
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessEqual),
+    (">=", Comparison::GreaterEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+/// Terms like `value == 0x00` or `pc >= $4000`, joined with `&&`. Numbers are hex.
+pub struct Condition {
+    text: String,
+    terms: Vec<(Operand, Comparison, u16)>,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let mut terms = Vec::new();
+
+        for term in text.split("&&") {
+            let (symbol, comparison) = COMPARISONS
+                .iter()
+                .find(|(symbol, _)| term.contains(symbol))
+                .ok_or_else(|| format!("no comparison in {:?}", term.trim()))?;
+            let (left, right) = term.split_at(term.find(symbol).unwrap());
+            let right = &right[symbol.len()..];
+
+            let operand = match left.trim() {
+                "value" | "v" => Operand::Value,
+                "address" | "addr" => Operand::Address,
+                "pc" => Operand::Pc,
+                other => return Err(format!("unknown operand {:?}", other)),
+            };
+            let number = parse_hex(right.trim()).ok_or_else(|| format!("bad number {:?}", right.trim()))?;
+
+            terms.push((operand, *comparison, number));
+        }
+
+        Ok(Condition {
+            text: text.trim().to_string(),
+            terms,
+        })
+    }
+
+    fn holds(&self, hit: &WatchHit) -> bool {
+        self.terms.iter().all(|(operand, comparison, number)| {
+            let left = match operand {
+                Operand::Value => hit.value as u16,
+                Operand::Address => hit.address,
+                Operand::Pc => hit.pc,
+            };
+            match comparison {
+                Comparison::Equal => left == *number,
+                Comparison::NotEqual => left != *number,
+                Comparison::Less => left < *number,
+                Comparison::LessEqual => left <= *number,
+                Comparison::Greater => left > *number,
+                Comparison::GreaterEqual => left >= *number,
+            }
+        })
+    }
+}
+
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+    pub condition: Option<Condition>,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{:04X}", self.start)?;
+        } else {
+            write!(f, "{:04X}-{:04X}", self.start, self.end)?;
+        }
+        let access = match self.access {
+            Access::Read => "r",
+            Access::Write => "w",
+            Access::ReadWrite => "rw",
+        };
+        write!(f, " {}", access)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// A memory access that tripped a watchpoint. `pc` is the instruction that made it.
+#[derive(Clone, Copy)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.write {
+            write!(f, "write {:02X} to {:04X} at PC {:04X}", self.value, self.address, self.pc)
+        } else {
+            write!(f, "read {:02X} from {:04X} at PC {:04X}", self.value, self.address, self.pc)
+        }
+    }
+}
+
+/// Watched address ranges, checked by the memory bus on every CPU read and write.
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    // start of the instruction being executed, set by the CPU.
+    pub pc: u16,
+    hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            list: Vec::new(),
+            pc: 0,
+            hit: None,
+        }
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.list.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    /// Returns the first access that matched since the last call.
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    pub fn check(&mut self, address: u16, value: u8, write: bool) {
+        if self.list.is_empty() || self.hit.is_some() {
+            return;
+        }
+
+        let hit = WatchHit {
+            pc: self.pc,
+            address,
+            value,
+            write,
+        };
+        let matched = self.list.iter().any(|watch| {
+            (watch.start..=watch.end).contains(&address)
+                && watch.access.matches(write)
+                && watch.condition.as_ref().is_none_or(|condition| condition.holds(&hit))
+        });
+        if matched {
+            self.hit = Some(hit);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::cpu::disassembler::{disassemble, Disassembly};
+use crate::cpu::symbols::Symbols;
+use crate::cpu::watchpoint::{Access, Condition, Watchpoint};
+use crate::cpu::CPU;
+use crate::memory_viewer::region_bytes;
+use crate::ram_search::{Filter, RamSearch};
+use crate::useful_func::parse_hex;
+
+const HELP: &str = "\
+commands :
+  c, continue            run until a breakpoint
+  s, step [n]            run n instructions (default 1)
+  n, next                step over CALL / RST
+  f, finish              run until the current function returns
+  b, break <addr>        add a breakpoint
+  d, delete <addr>       remove a breakpoint
+  bl, breakpoints        list breakpoints
+  w, watch <addr>[-<end>] [r|w|rw] [if <cond>]
+                         break after an access, e.g. watch c000-c0ff w if value == 0
+                         conditions compare value, address or pc, joined with &&
+  dw, unwatch <n>        remove watchpoint number n
+  wl, watchpoints        list watchpoints
+  r, regs                show registers and flags
+  x <addr> [len]         examine memory
+  set <addr> <value>     write a byte through the memory bus
+  l, list [addr] [n]     disassemble n instructions (default 10 from PC)
+  dump <region> <file>   write memory to a file. regions : all, ROM0, ROMX, VRAM, SRAM,
+                         WRAM, OAM, IO, HRAM, rom, rom<n>, ram, ram<n> or <start>-<end>
+  search start           snapshot WRAM, HRAM and cartridge RAM for a value search
+  search <filter>        keep the bytes that compare with the last search like this :
+                         = (same), != (changed), > (increased), < (decreased) or a value
+  search list [n]        show n surviving addresses (default 20)
+  q, quit                exit the emulator
+addresses, values and lengths are hex, with or without a $ or 0x prefix. counts
+(step n, list n, search list n) are decimal. addresses can also be labels from
+the <rom>.sym file next to the ROM.
+an empty line repeats the last command.";
+
+/// What the emulator should do once the prompt gives control back.
+pub enum DebugAction {
+    Resume,
+    Quit,
+}
+
+/// Interactive debugger on stdin / stdout. The emulator asks `should_break` before
+/// every instruction and runs `repl` when it says yes.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    break_requested: bool,
+    steps_left: u32,
+    step_over: Option<u16>,
+    finish_sp: Option<u16>,
+    last_command: String,
+    ram_search: RamSearch,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            break_requested: false,
+            steps_left: 0,
+            step_over: None,
+            finish_sp: None,
+            last_command: String::new(),
+            ram_search: RamSearch::new(),
+        }
+    }
+
+    /// Stops before the next instruction.
+    pub fn break_now(&mut self) {
+        self.break_requested = true;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn should_break(&mut self, cpu: &mut CPU) -> bool {
+        if let Some(hit) = cpu.bus.watch.take_hit() {
+            println!("watchpoint : {}", hit);
+            return true;
+        }
+
+        if self.break_requested {
+            self.break_requested = false;
+            return true;
+        }
+
+        if self.steps_left > 0 {
+            self.steps_left -= 1;
+            if self.steps_left == 0 {
+                return true;
+            }
+        }
+
+        // nothing executes while halted, so only stepping counts there.
+        if cpu.is_halted {
+            return false;
+        }
+
+        if self.step_over == Some(cpu.pc) {
+            self.step_over = None;
+            return true;
+        }
+
+        if let Some(sp) = self.finish_sp {
+            // the return address has been popped.
+            if cpu.sp > sp {
+                self.finish_sp = None;
+                return true;
+            }
+        }
+
+        self.breakpoints.contains(&cpu.pc)
+    }
+
+    /// Reads commands until one of them resumes execution.
+    pub fn repl(&mut self, cpu: &mut CPU) -> DebugAction {
+        self.steps_left = 0;
+        self.step_over = None;
+        self.finish_sp = None;
+
+        self.print_current(cpu);
+
+        loop {
+            print!("(ripboy) ");
+            io::stdout().flush().ok();
+
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    // no terminal to talk to, let the game run.
+                    self.breakpoints.clear();
+                    return DebugAction::Resume;
+                }
+                Ok(_) => (),
+            }
+
+            let mut line = line.trim().to_string();
+            if line.is_empty() {
+                line = self.last_command.clone();
+            } else {
+                self.last_command = line.clone();
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+
+            match args[0] {
+                "c" | "continue" => return DebugAction::Resume,
+
+                "s" | "step" => {
+                    self.steps_left = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(1).max(1);
+                    return DebugAction::Resume;
+                }
+
+                "n" | "next" => {
+                    let current = self.disassemble_at(cpu, cpu.pc);
+                    if current.is_call() {
+                        self.step_over = Some(cpu.pc.wrapping_add(current.len()));
+                    } else {
+                        self.steps_left = 1;
+                    }
+                    return DebugAction::Resume;
+                }
+
+                "f" | "finish" => {
+                    self.finish_sp = Some(cpu.sp);
+                    return DebugAction::Resume;
+                }
+
+                "b" | "break" => match args.get(1).and_then(|a| parse_address(cpu, a)) {
+                    Some(address) => {
+                        self.add_breakpoint(address);
+                        println!("breakpoint at {:04X}", address);
+                    }
+                    None => println!("usage : break <addr>"),
+                },
+
+                "d" | "delete" => match args.get(1).and_then(|a| parse_address(cpu, a)) {
+                    Some(address) => {
+                        if self.remove_breakpoint(address) {
+                            println!("removed breakpoint at {:04X}", address);
+                        } else {
+                            println!("no breakpoint at {:04X}", address);
+                        }
+                    }
+                    None => println!("usage : delete <addr>"),
+                },
+
+                "bl" | "breakpoints" => {
+                    if self.breakpoints.is_empty() {
+                        println!("no breakpoints");
+                    }
+                    let (rom_bank, ram_bank) = switched_banks(cpu);
+                    for address in self.breakpoints.iter() {
+                        match cpu.symbols.locate(*address, rom_bank, ram_bank) {
+                            Some(name) => println!("  {:04X} {}", address, name),
+                            None => println!("  {:04X}", address),
+                        }
+                    }
+                }
+
+                "w" | "watch" => match parse_watchpoint(&args[1..]) {
+                    Ok(watchpoint) => {
+                        println!("watchpoint {} : {}", cpu.bus.watch.list().len(), watchpoint);
+                        cpu.bus.watch.add(watchpoint);
+                    }
+                    Err(error) => println!("{}", error),
+                },
+
+                "dw" | "unwatch" => match args.get(1).and_then(|n| n.parse().ok()) {
+                    Some(index) => match cpu.bus.watch.remove(index) {
+                        Some(watchpoint) => println!("removed watchpoint {}", watchpoint),
+                        None => println!("no watchpoint {}", index),
+                    },
+                    None => println!("usage : unwatch <n>"),
+                },
+
+                "wl" | "watchpoints" => {
+                    if cpu.bus.watch.list().is_empty() {
+                        println!("no watchpoints");
+                    }
+                    for (i, watchpoint) in cpu.bus.watch.list().iter().enumerate() {
+                        println!("  {} : {}", i, watchpoint);
+                    }
+                }
+
+                "r" | "regs" => print_registers(cpu),
+
+                "x" => match args.get(1).and_then(|a| parse_address(cpu, a)) {
+                    Some(address) => {
+                        let len = args.get(2).and_then(|a| parse_hex(a)).unwrap_or(0x40);
+                        self.examine(cpu, address, len);
+                    }
+                    None => println!("usage : x <addr> [len]"),
+                },
+
+                "set" => match (
+                    args.get(1).and_then(|a| parse_address(cpu, a)),
+                    args.get(2).and_then(|a| parse_hex(a)),
+                ) {
+                    (Some(address), Some(value)) => {
+                        cpu.bus.poke(address, value as u8);
+                        println!("{:04X} = {:02X}", address, cpu.bus.peek(address));
+                    }
+                    _ => println!("usage : set <addr> <value>"),
+                },
+
+                "l" | "list" => {
+                    let address = args.get(1).and_then(|a| parse_address(cpu, a)).unwrap_or(cpu.pc);
+                    let count = args.get(2).and_then(|a| a.parse().ok()).unwrap_or(10);
+                    self.list(cpu, address, count);
+                }
+
+                "dump" => match (args.get(1), args.get(2)) {
+                    (Some(region), Some(file)) => match region_bytes(&mut cpu.bus, region) {
+                        Ok(data) => match fs::write(file, &data) {
+                            Ok(()) => println!("wrote {} bytes to {}", data.len(), file),
+                            Err(error) => println!("could not write {} : {}", file, error),
+                        },
+                        Err(error) => println!("{}", error),
+                    },
+                    _ => println!("usage : dump <region> <file>"),
+                },
+
+                "search" => self.search(cpu, &args[1..]),
+
+                "q" | "quit" => return DebugAction::Quit,
+
+                "h" | "help" => println!("{}", HELP),
+
+                _ => println!("unknown command {:?}, try help", args[0]),
+            }
+        }
+    }
+
+    fn disassemble_at(&self, cpu: &mut CPU, address: u16) -> Disassembly {
+        disassemble(address, |a| cpu.bus.peek(a))
+    }
+
+    fn print_current(&self, cpu: &mut CPU) {
+        let current = self.disassemble_at(cpu, cpu.pc);
+        let banks = switched_banks(cpu);
+        if let Some(name) = cpu.symbols.locate(cpu.pc, banks.0, banks.1) {
+            println!("{}:", name);
+        }
+        println!("{}", format_line(&current, true, &cpu.symbols, banks));
+    }
+
+    fn list(&self, cpu: &mut CPU, mut address: u16, count: u32) {
+        for _ in 0..count {
+            let line = self.disassemble_at(cpu, address);
+            let banks = switched_banks(cpu);
+            if let Some(label) = cpu.symbols.label(line.address, banks.0, banks.1) {
+                println!("{}:", label);
+            }
+            println!("{}", format_line(&line, line.address == cpu.pc, &cpu.symbols, banks));
+            address = address.wrapping_add(line.len());
+        }
+    }
+
+    fn search(&mut self, cpu: &CPU, args: &[&str]) {
+        let search = &mut self.ram_search;
+        match args.first() {
+            Some(&"start") => {
+                search.start(&cpu.bus);
+                println!("{} bytes to search", search.candidates().len());
+            }
+            Some(&"list") => {
+                let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(20);
+                for candidate in search.candidates().iter().take(count) {
+                    println!(
+                        "{}  {:02X} -> {:02X}",
+                        candidate.location, candidate.previous, candidate.current
+                    );
+                }
+                if search.candidates().len() > count {
+                    println!("... {} more", search.candidates().len() - count);
+                }
+            }
+            Some(filter) => match Filter::parse(filter) {
+                Some(_) if !search.is_started() => println!("no search yet, use search start"),
+                Some(filter) => println!("{} bytes left", search.filter(&cpu.bus, filter)),
+                None => println!("unknown search filter {:?}", filter),
+            },
+            None => println!("usage : search start | = | != | > | < | <value> | list [n]"),
+        }
+    }
+
+    fn examine(&self, cpu: &mut CPU, address: u16, len: u16) {
+        let mut line = String::new();
+        for i in 0..len {
+            let at = address.wrapping_add(i);
+            if i % 16 == 0 {
+                if !line.is_empty() {
+                    println!("{}", line);
+                }
+                line = format!("{:04X}:", at);
+            }
+            line.push_str(&format!(" {:02X}", cpu.bus.peek(at)));
+        }
+        if !line.is_empty() {
+            println!("{}", line);
+        }
+    }
+}
+
+/// An instruction with its jump or call target named, when a symbol is there.
+fn format_line(line: &Disassembly, current: bool, symbols: &Symbols, banks: (u16, u16)) -> String {
+    let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let target = line.target.and_then(|target| symbols.label(target, banks.0, banks.1));
+    format!(
+        "{} {:04X}: {:<9} {}{}",
+        if current { "=>" } else { "  " },
+        line.address,
+        bytes.join(" "),
+        line.text,
+        target.map_or(String::new(), |name| format!(" <{}>", name))
+    )
+}
+
+/// The ROM and RAM banks switched in, to look symbols up with.
+fn switched_banks(cpu: &CPU) -> (u16, u16) {
+    (cpu.bus.current_rom_bank() as u16, cpu.bus.current_ram_bank() as u16)
+}
+
+/// A label from the symbol file or a hex address. Addresses don't carry a bank,
+/// so a label in a switchable bank also matches whatever else is switched in there.
+fn parse_address(cpu: &CPU, text: &str) -> Option<u16> {
+    match cpu.symbols.address_of(text) {
+        Some((bank, address)) => {
+            let banked = matches!(address, 0x4000..=0x7FFF | 0xA000..=0xBFFF);
+            if bank > 0 && banked {
+                println!("note : {} is in bank {:X}, {:04X} is used in any bank", text, bank, address);
+            }
+            Some(address)
+        }
+        None => parse_hex(text),
+    }
+}
+
+fn print_registers(cpu: &CPU) {
+    let r = &cpu.registers;
+    println!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X}",
+        r.a,
+        r.f.con(),
+        r.b,
+        r.c,
+        r.d,
+        r.e,
+        r.h,
+        r.l,
+        cpu.sp,
+        cpu.pc
+    );
+    println!(
+        "flags : {}{}{}{}  IME:{} halted:{}",
+        if r.f.zero { 'Z' } else { '-' },
+        if r.f.subtract { 'N' } else { '-' },
+        if r.f.half_carry { 'H' } else { '-' },
+        if r.f.carry { 'C' } else { '-' },
+        cpu.bus.interupt_master as u8,
+        cpu.is_halted
+    );
+}
+
+/// Parses `<addr>[-<end>] [r|w|rw] [if <cond>]`. Without an access kind it watches writes.
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let usage = String::from("usage : watch <addr>[-<end>] [r|w|rw] [if <cond>]");
+
+    let range = args.first().ok_or_else(|| usage.clone())?;
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_hex(start), parse_hex(end)),
+        None => (parse_hex(range), parse_hex(range)),
+    };
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) if start <= end => (start, end),
+        _ => return Err(usage),
+    };
+
+    let mut rest = &args[1..];
+    let mut access = Access::Write;
+    if let Some(kind) = rest.first().and_then(|a| Access::parse(a)) {
+        access = kind;
+        rest = &rest[1..];
+    }
+
+    let condition = match rest.split_first() {
+        None => None,
+        Some((&"if", condition)) => Some(Condition::parse(&condition.join(" "))?),
+        Some(_) => return Err(usage),
+    };
+
+    Ok(Watchpoint {
+        start,
+        end,
+        access,
+        condition,
+    })
+}
@@ -0,0 +1,370 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::cpu::CPU;
+
+// registers as GDB sees them, 16 bits each and little endian : AF BC DE HL SP PC.
+const REGISTER_COUNT: usize = 6;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+// the most bytes a memory read answers with, two hex digits each in a 0x1000 byte packet.
+const MAX_READ: usize = 0x800;
+
+/// What the emulator should do once the stub gives control back.
+pub enum GdbAction {
+    Resume,
+    Quit,
+}
+
+/// GDB remote serial protocol server on a local TCP port. Like `Debugger`, the
+/// emulator asks `should_break` before every instruction and hands the CPU to
+/// `serve` while GDB has it stopped.
+pub struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    breakpoints: BTreeSet<u16>,
+    stop_signal: Option<u8>,
+    stepping: bool,
+    // GDB is waiting for a stop reply to its last continue or step.
+    running: bool,
+}
+
+impl GdbStub {
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            stream: None,
+            breakpoints: BTreeSet::new(),
+            stop_signal: None,
+            stepping: false,
+            running: false,
+        })
+    }
+
+    /// Picks up a new connection or a Ctrl-C from GDB. Called once a frame.
+    pub fn poll(&mut self) {
+        if self.stream.is_none() {
+            if let Ok((stream, address)) = self.listener.accept() {
+                println!("GDB connected from {}", address);
+                stream.set_nonblocking(false).ok();
+                stream.set_nodelay(true).ok();
+                self.stream = Some(stream);
+                self.breakpoints.clear();
+                // a fresh connection expects the target to be stopped.
+                self.stop_signal = Some(SIGTRAP);
+                self.running = false;
+            }
+            return;
+        }
+
+        let mut interrupted = false;
+        let mut closed = false;
+        if let Some(stream) = &mut self.stream {
+            stream.set_nonblocking(true).ok();
+            let mut byte = [0; 1];
+            match stream.read(&mut byte) {
+                Ok(0) => closed = true,
+                Ok(_) => interrupted = byte[0] == 0x03,
+                Err(error) => closed = error.kind() != io::ErrorKind::WouldBlock,
+            }
+            stream.set_nonblocking(false).ok();
+        }
+
+        if closed {
+            self.disconnect();
+        } else if interrupted {
+            self.stop_signal = Some(SIGINT);
+        }
+    }
+
+    pub fn should_break(&mut self, cpu: &CPU) -> bool {
+        if self.stream.is_none() {
+            return false;
+        }
+
+        if self.stop_signal.is_some() {
+            return true;
+        }
+
+        if self.stepping || (!cpu.is_halted && self.breakpoints.contains(&cpu.pc)) {
+            self.stop_signal = Some(SIGTRAP);
+            return true;
+        }
+        false
+    }
+
+    /// Answers packets until GDB continues, steps, detaches or kills the target.
+    pub fn serve(&mut self, cpu: &mut CPU) -> GdbAction {
+        self.stepping = false;
+        let signal = self.stop_signal.take().unwrap_or(SIGTRAP);
+
+        if self.running {
+            self.running = false;
+            if self.send(&format!("S{:02X}", signal)).is_err() {
+                self.disconnect();
+                return GdbAction::Resume;
+            }
+        }
+
+        loop {
+            let packet = match self.receive() {
+                Ok(packet) => packet,
+                Err(_) => {
+                    self.disconnect();
+                    return GdbAction::Resume;
+                }
+            };
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'?') => format!("S{:02X}", signal),
+                Some(b'g') => read_registers(cpu),
+                Some(b'G') => write_registers(cpu, &packet[1..]),
+                Some(b'p') => read_register(cpu, &packet[1..]),
+                Some(b'P') => write_register(cpu, &packet[1..]),
+                Some(b'm') => read_memory(cpu, &packet[1..]),
+                Some(b'M') => write_memory(cpu, &packet[1..]),
+                Some(b'Z') => self.set_breakpoint(&packet[1..], true),
+                Some(b'z') => self.set_breakpoint(&packet[1..], false),
+                Some(b'H') => String::from("OK"),
+                Some(b'c') => {
+                    resume_at(cpu, &packet[1..]);
+                    self.running = true;
+                    return GdbAction::Resume;
+                }
+                Some(b's') => {
+                    resume_at(cpu, &packet[1..]);
+                    self.running = true;
+                    self.stepping = true;
+                    return GdbAction::Resume;
+                }
+                Some(b'D') => {
+                    self.send("OK").ok();
+                    self.disconnect();
+                    return GdbAction::Resume;
+                }
+                Some(b'k') => return GdbAction::Quit,
+                Some(b'q') => query(&packet),
+                _ => String::new(),
+            };
+
+            if self.send(&reply).is_err() {
+                self.disconnect();
+                return GdbAction::Resume;
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if self.stream.take().is_some() {
+            println!("GDB disconnected");
+        }
+        self.breakpoints.clear();
+        self.stop_signal = None;
+        self.stepping = false;
+        self.running = false;
+    }
+
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let address = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+
+        match (kind, address) {
+            // software and hardware breakpoints are the same thing here.
+            (Some("0"), Some(address)) | (Some("1"), Some(address)) => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                String::from("OK")
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Reads one `$data#checksum` packet and acknowledges it.
+    fn receive(&mut self) -> io::Result<String> {
+        let stream = self.stream.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        let mut byte = [0; 1];
+
+        loop {
+            // skip acks and stray interrupts until a packet starts.
+            loop {
+                stream.read_exact(&mut byte)?;
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                stream.read_exact(&mut byte)?;
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+            if expected == Some(packet_checksum(&data)) {
+                stream.write_all(b"+")?;
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+            stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let stream = self.stream.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        let packet = format!("${}#{:02x}", data, packet_checksum(data.as_bytes()));
+
+        loop {
+            stream.write_all(packet.as_bytes())?;
+            let mut ack = [0; 1];
+            stream.read_exact(&mut ack)?;
+            if ack[0] != b'-' {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        format!("PacketSize={:x}", MAX_READ * 2)
+    } else if packet == "qAttached" {
+        String::from("1")
+    } else if packet == "qfThreadInfo" {
+        String::from("m1")
+    } else if packet == "qsThreadInfo" {
+        String::from("l")
+    } else if packet == "qC" {
+        String::from("QC1")
+    } else {
+        String::new()
+    }
+}
+
+fn get_register(cpu: &CPU, index: usize) -> u16 {
+    let r = &cpu.registers;
+    match index {
+        0 => (r.a as u16) << 8 | r.f.con() as u16,
+        1 => r.get_bc(),
+        2 => r.get_de(),
+        3 => r.get_hl(),
+        4 => cpu.sp,
+        _ => cpu.pc,
+    }
+}
+
+fn set_register(cpu: &mut CPU, index: usize, value: u16) {
+    match index {
+        0 => cpu.registers.set_af(value),
+        1 => cpu.registers.set_bc(value),
+        2 => cpu.registers.set_de(value),
+        3 => cpu.registers.set_hl(value),
+        4 => cpu.sp = value,
+        _ => cpu.pc = value,
+    }
+}
+
+/// GDB sends register values in target byte order, i.e. little endian.
+fn parse_le_word(hex: &str) -> Option<u16> {
+    let value = u16::from_str_radix(hex.get(0..4)?, 16).ok()?;
+    Some(value.swap_bytes())
+}
+
+fn read_registers(cpu: &CPU) -> String {
+    (0..REGISTER_COUNT)
+        .map(|i| format!("{:04x}", get_register(cpu, i).swap_bytes()))
+        .collect()
+}
+
+fn write_registers(cpu: &mut CPU, hex: &str) -> String {
+    for i in 0..REGISTER_COUNT {
+        match hex.get(i * 4..).and_then(parse_le_word) {
+            Some(value) => set_register(cpu, i, value),
+            None => return String::from("E01"),
+        }
+    }
+    String::from("OK")
+}
+
+fn read_register(cpu: &CPU, args: &str) -> String {
+    match usize::from_str_radix(args, 16) {
+        Ok(index) if index < REGISTER_COUNT => format!("{:04x}", get_register(cpu, index).swap_bytes()),
+        _ => String::from("E01"),
+    }
+}
+
+fn write_register(cpu: &mut CPU, args: &str) -> String {
+    let mut fields = args.split('=');
+    let index = fields.next().and_then(|i| usize::from_str_radix(i, 16).ok());
+    let value = fields.next().and_then(parse_le_word);
+
+    match (index, value) {
+        (Some(index), Some(value)) if index < REGISTER_COUNT => {
+            set_register(cpu, index, value);
+            String::from("OK")
+        }
+        _ => String::from("E01"),
+    }
+}
+
+/// Parses `addr,length`.
+fn parse_range(args: &str) -> Option<(u16, usize)> {
+    let mut fields = args.split(',');
+    let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let length = usize::from_str_radix(fields.next()?, 16).ok()?;
+    Some((address, length))
+}
+
+/// Any address reads, the unusable area as 0xFF. Long reads are cut short, which
+/// GDB allows, so a huge length can't stall the emulator.
+fn read_memory(cpu: &mut CPU, args: &str) -> String {
+    match parse_range(args) {
+        Some((address, length)) => (0..length.min(MAX_READ))
+            .map(|i| format!("{:02x}", cpu.bus.peek(address.wrapping_add(i as u16))))
+            .collect(),
+        None => String::from("E01"),
+    }
+}
+
+fn write_memory(cpu: &mut CPU, args: &str) -> String {
+    let mut fields = args.split(':');
+    let range = fields.next().and_then(parse_range);
+    let data = fields.next().unwrap_or("");
+
+    match range {
+        Some((address, length)) if data.len() >= length * 2 => {
+            for i in 0..length {
+                match u8::from_str_radix(&data[i * 2..i * 2 + 2], 16) {
+                    Ok(value) => cpu.bus.poke(address.wrapping_add(i as u16), value),
+                    Err(_) => return String::from("E01"),
+                }
+            }
+            String::from("OK")
+        }
+        _ => String::from("E01"),
+    }
+}
+
+/// `c` and `s` may carry an address to resume from.
+fn resume_at(cpu: &mut CPU, args: &str) {
+    if let Ok(address) = u16::from_str_radix(args, 16) {
+        cpu.pc = address;
+    }
+}
@@ -1,11 +1,22 @@
+pub mod overlay;
 pub mod tile_pixel_value;
+pub mod vram_viewer;
 
 // use crate::cpu::memory_map::*;
+use crate::cpu::state::{StateReader, StateWriter};
 use crate::useful_func::*;
+use std::io;
 use tile_pixel_value::TilePixelValue;
 
 type Tile = [[TilePixelValue; 8]; 8];
 
+// shades for colour numbers 0 (lightest) to 3 (darkest).
+pub const PALETTES: [(&str, [u32; 4]); 3] = [
+    ("Green", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+    ("Grey", [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]),
+    ("Pocket", [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+];
+
 pub fn empty_tile() -> Tile {
     [[TilePixelValue::Zero; 8]; 8]
 }
@@ -17,6 +28,7 @@ pub struct GPU {
     _line: u8,
 
     pub buffer: [u32; 160 * 144],
+    pub palette: [u32; 4],
 }
 
 // impl defa
@@ -30,6 +42,7 @@ impl GPU {
             _line: 0,
 
             buffer: [0; 160 * 144],
+            palette: PALETTES[0].1,
         }
     }
 
@@ -98,12 +111,35 @@ impl GPU {
 
     fn render_scan(&self) {}
 
-    // pub fn read_vram(&self, address: usize) -> u8 {
-    //     self.vram[address]
-    // }
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self._modeclock);
+        w.u8(self._mode);
+        w.u8(self._line);
+        for pixel in self.buffer.iter() {
+            w.u32(*pixel);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self._modeclock = r.u16()?;
+        self._mode = r.u8()?;
+        self._line = r.u8()?;
+        for pixel in self.buffer.iter_mut() {
+            *pixel = r.u32()?;
+        }
+        Ok(())
+    }
+
+    /// Decodes every tile again, e.g. after VRAM was replaced by a save state.
+    pub fn rebuild_tile_set(&mut self, vram: &[u8]) {
+        for index in (0..0x1800).step_by(2) {
+            self.write_vram(vram, index);
+        }
+    }
 
-    /*pub fn write_vram(&mut self, index: usize, value: u8) {
-        self.vram[index] = value;
+    /// Keeps `tile_set` in step with VRAM. `vram` is 0x8000-0x9FFF, already holding
+    /// the new byte at `index`.
+    pub fn write_vram(&mut self, vram: &[u8], index: usize) {
         // If our index is greater than 0x1800, we're not writing to the tile set storage
         // so we can just return.
         if index >= 0x1800 {
@@ -117,8 +153,8 @@ impl GPU {
         let normalized_index = index & 0xFFFE;
 
         // First we need to get the two bytes that encode the tile row.
-        let byte1 = self.vram[normalized_index];
-        let byte2 = self.vram[normalized_index + 1];
+        let byte1 = vram[normalized_index];
+        let byte2 = vram[normalized_index + 1];
 
         // A tiles is 8 rows tall. Since each row is encoded with two bytes a tile
         // is therefore 16 bytes in total.
@@ -164,5 +200,4 @@ impl GPU {
             self.tile_set[tile_index][row_index][pixel_index] = value;
         }
     }
-    */
 }
@@ -0,0 +1,203 @@
+use std::time::{Duration, Instant};
+
+use crate::useful_func::*;
+
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+pub const CHAR_ADVANCE: usize = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+const NOTIFICATION_TIME: Duration = Duration::from_secs(2);
+
+// 5x7 glyphs for ' ' to '_', one byte per row with bit 4 as the leftmost pixel.
+const FONT: [[u8; GLYPH_HEIGHT]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x00, 0x00, 0x04], // !
+    [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A], // #
+    [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D], // &
+    [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00], // '
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F], // 2
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E], // @
+    [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11], // A
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E], // B
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C], // D
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F], // E
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10], // F
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F], // G
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // H
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F], // L
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // O
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D], // Q
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E], // S
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A], // W
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04], // Y
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F], // Z
+    [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // \
+    [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E], // ]
+    [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // _
+];
+
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    match c {
+        ' '..='_' => &FONT[c as usize - ' ' as usize],
+        _ => &FONT['?' as usize - ' ' as usize],
+    }
+}
+
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * CHAR_ADVANCE
+}
+
+/// Draws `text` with its top left corner at (x, y), clipping at the buffer edges.
+pub fn draw_text(buffer: &mut [u32], width: usize, x: usize, y: usize, text: &str, color: u32) {
+    let height = buffer.len() / width;
+
+    for (i, c) in text.chars().enumerate() {
+        let rows = glyph(c);
+        let left = x + i * CHAR_ADVANCE;
+
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                let (px, py) = (left + col, y + row);
+                if px < width && py < height {
+                    buffer[py * width + px] = color;
+                }
+            }
+        }
+    }
+}
+
+pub fn fill_rect(buffer: &mut [u32], width: usize, x: usize, y: usize, w: usize, h: usize, color: u32) {
+    let height = buffer.len() / width;
+
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            buffer[py * width + px] = color;
+        }
+    }
+}
+
+/// Draws a boxed list centred on screen, with a marker in front of the selected item.
+pub fn draw_menu(buffer: &mut [u32], width: usize, title: &str, items: &[String], selected: usize) {
+    let height = buffer.len() / width;
+
+    let longest = items.iter().map(|item| text_width(item) + CHAR_ADVANCE).max().unwrap_or(0);
+    let box_w = longest.max(text_width(title)) + 8;
+    let box_h = (items.len() + 1) * LINE_HEIGHT + 8;
+    let x = width.saturating_sub(box_w) / 2;
+    let y = height.saturating_sub(box_h) / 2;
+
+    fill_rect(buffer, width, x, y, box_w, box_h, from_u8_rgb(255, 255, 255));
+    fill_rect(buffer, width, x + 1, y + 1, box_w - 2, box_h - 2, from_u8_rgb(0, 0, 0));
+    draw_text(buffer, width, x + 4, y + 4, title, from_u8_rgb(255, 255, 0));
+
+    for (i, item) in items.iter().enumerate() {
+        let line_y = y + 4 + (i + 1) * LINE_HEIGHT;
+        let color = if i == selected {
+            draw_text(buffer, width, x + 4, line_y, ">", from_u8_rgb(255, 255, 255));
+            from_u8_rgb(255, 255, 255)
+        } else {
+            from_u8_rgb(160, 160, 160)
+        };
+        draw_text(buffer, width, x + 4 + CHAR_ADVANCE, line_y, item, color);
+    }
+}
+
+/// Text drawn over the game screen : transient notifications and the FPS counter.
+pub struct Overlay {
+    notification: Option<(String, Instant)>,
+    pub show_fps: bool,
+    frames: u32,
+    fps: u32,
+    fps_since: Instant,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            notification: None,
+            show_fps: false,
+            frames: 0,
+            fps: 0,
+            fps_since: Instant::now(),
+        }
+    }
+
+    pub fn notify(&mut self, message: &str) {
+        self.notification = Some((message.to_string(), Instant::now()));
+    }
+
+    /// Counts an emulated frame towards the FPS counter.
+    pub fn frame(&mut self) {
+        self.frames += 1;
+        if self.fps_since.elapsed() >= Duration::from_secs(1) {
+            self.fps = self.frames;
+            self.frames = 0;
+            self.fps_since = Instant::now();
+        }
+    }
+
+    pub fn draw(&mut self, buffer: &mut [u32], width: usize, speed: &str) {
+        let height = buffer.len() / width;
+
+        if self.show_fps {
+            let text = format!("{} FPS {}", self.fps, speed);
+            fill_rect(buffer, width, 0, 0, text_width(&text) + 2, LINE_HEIGHT, 0);
+            draw_text(buffer, width, 1, 1, &text, from_u8_rgb(255, 255, 255));
+        }
+
+        if let Some((message, since)) = &self.notification {
+            if since.elapsed() < NOTIFICATION_TIME {
+                let y = height - LINE_HEIGHT;
+                fill_rect(buffer, width, 0, y, text_width(message) + 2, LINE_HEIGHT, 0);
+                draw_text(buffer, width, 1, y + 1, message, from_u8_rgb(255, 255, 255));
+            } else {
+                self.notification = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::overlay::{draw_text, fill_rect, LINE_HEIGHT};
+use super::tile_pixel_value::TilePixelValue;
+use super::GPU;
+use crate::useful_func::*;
+
+// tile sheet on the left, the two maps next to it and the OAM table underneath.
+const TILES_X: usize = 0;
+const MAP_9800_X: usize = 136;
+const MAP_9C00_X: usize = 400;
+const LABEL_Y: usize = 260;
+const OAM_Y: usize = 274;
+const OAM_ROWS: usize = 20;
+const OAM_COLUMN_WIDTH: usize = 328;
+
+pub const VIEWER_WIDTH: usize = MAP_9C00_X + 256;
+pub const VIEWER_HEIGHT: usize = OAM_Y + OAM_ROWS * LINE_HEIGHT;
+
+const BACKGROUND: u32 = 0x202020;
+const VIEWPORT: u32 = 0xFF0000;
+const WINDOW: u32 = 0x0080FF;
+
+fn colour_number(value: TilePixelValue) -> usize {
+    match value {
+        TilePixelValue::Zero => 0,
+        TilePixelValue::One => 1,
+        TilePixelValue::Two => 2,
+        TilePixelValue::Three => 3,
+    }
+}
+
+/// Shade for a background colour number, through BGP and the display palette.
+fn bg_shade(gpu: &GPU, memory: &[u8], value: TilePixelValue) -> u32 {
+    let bgp = memory[0xFF47];
+    let shade = (bgp >> (colour_number(value) * 2)) & 0x03;
+    gpu.palette[shade as usize]
+}
+
+fn draw_tile(buffer: &mut [u32], gpu: &GPU, memory: &[u8], tile: usize, x: usize, y: usize) {
+    for (row, pixels) in gpu.tile_set[tile].iter().enumerate() {
+        for (col, value) in pixels.iter().enumerate() {
+            buffer[(y + row) * VIEWER_WIDTH + x + col] = bg_shade(gpu, memory, *value);
+        }
+    }
+}
+
+/// Draws a w x h outline at (x, y) inside a 256x256 map, wrapping like the hardware does.
+fn draw_wrapped_rect(buffer: &mut [u32], left: usize, x: u8, y: u8, w: usize, h: usize, color: u32) {
+    let mut plot = |px: usize, py: usize| {
+        let px = (x as usize + px) % 256;
+        let py = (y as usize + py) % 256;
+        buffer[py * VIEWER_WIDTH + left + px] = color;
+    };
+
+    for i in 0..w {
+        plot(i, 0);
+        plot(i, h - 1);
+    }
+    for i in 0..h {
+        plot(0, i);
+        plot(w - 1, i);
+    }
+}
+
+fn draw_map(buffer: &mut [u32], gpu: &GPU, memory: &[u8], base: usize, left: usize) {
+    let unsigned = test_bit(memory[0xFF40], 4);
+
+    for i in 0..32 * 32 {
+        let number = memory[base + i];
+        // with LCDC bit 4 clear, tiles are numbered -128 to 127 around 0x9000.
+        let tile = if unsigned {
+            number as usize
+        } else {
+            (256 + number as i8 as i16) as usize
+        };
+        draw_tile(buffer, gpu, memory, tile, left + (i % 32) * 8, (i / 32) * 8);
+    }
+}
+
+/// One line per OAM entry : position, tile, palette, flips and BG priority.
+pub fn oam_table(memory: &[u8]) -> Vec<String> {
+    (0..40)
+        .map(|i| {
+            let entry = &memory[0xFE00 + i * 4..0xFE00 + i * 4 + 4];
+            let flags = entry[3];
+            format!(
+                "{:02} Y:{:3} X:{:3} T:{:02X} OBP{} {}{} {}",
+                i,
+                entry[0],
+                entry[1],
+                entry[2],
+                if test_bit(flags, 4) { 1 } else { 0 },
+                if test_bit(flags, 5) { 'X' } else { '-' },
+                if test_bit(flags, 6) { 'Y' } else { '-' },
+                if test_bit(flags, 7) { "BEHIND" } else { "" }
+            )
+        })
+        .collect()
+}
+
+/// Renders the tile sheet, both tile maps and the OAM table into a
+/// `VIEWER_WIDTH` x `VIEWER_HEIGHT` buffer.
+pub fn render(gpu: &GPU, memory: &[u8], buffer: &mut [u32]) {
+    for pixel in buffer.iter_mut() {
+        *pixel = BACKGROUND;
+    }
+
+    // 384 tiles, 16 to a row.
+    for tile in 0..384 {
+        draw_tile(buffer, gpu, memory, tile, TILES_X + (tile % 16) * 8, (tile / 16) * 8);
+    }
+
+    draw_map(buffer, gpu, memory, 0x9800, MAP_9800_X);
+    draw_map(buffer, gpu, memory, 0x9C00, MAP_9C00_X);
+
+    let control = memory[0xFF40];
+    let bg_x = if test_bit(control, 3) { MAP_9C00_X } else { MAP_9800_X };
+    let window_x = if test_bit(control, 6) { MAP_9C00_X } else { MAP_9800_X };
+
+    draw_wrapped_rect(buffer, bg_x, memory[0xFF43], memory[0xFF42], 160, 144, VIEWPORT);
+
+    // the part of the window map that fits on screen, when the window is on.
+    let (wx, wy) = (memory[0xFF4B].saturating_sub(7) as usize, memory[0xFF4A] as usize);
+    if test_bit(control, 5) && wx < 160 && wy < 144 {
+        draw_wrapped_rect(buffer, window_x, 0, 0, 160 - wx, 144 - wy, WINDOW);
+    }
+
+    let white = from_u8_rgb(255, 255, 255);
+    draw_text(buffer, VIEWER_WIDTH, TILES_X, 196, "TILES", white);
+    draw_text(buffer, VIEWER_WIDTH, TILES_X, 196 + LINE_HEIGHT, "8000-97FF", white);
+    let label = |base: usize, x: usize| {
+        let mut text = format!("{:04X}", base);
+        if x == bg_x {
+            text.push_str(" BG");
+        }
+        if x == window_x {
+            text.push_str(" WINDOW");
+        }
+        text
+    };
+    draw_text(buffer, VIEWER_WIDTH, MAP_9800_X, LABEL_Y, &label(0x9800, MAP_9800_X), white);
+    draw_text(buffer, VIEWER_WIDTH, MAP_9C00_X, LABEL_Y, &label(0x9C00, MAP_9C00_X), white);
+
+    fill_rect(buffer, VIEWER_WIDTH, 0, OAM_Y - 2, VIEWER_WIDTH, 1, from_u8_rgb(96, 96, 96));
+    for (i, line) in oam_table(memory).iter().enumerate() {
+        let x = (i / OAM_ROWS) * OAM_COLUMN_WIDTH;
+        let y = OAM_Y + (i % OAM_ROWS) * LINE_HEIGHT;
+        draw_text(buffer, VIEWER_WIDTH, x, y, line, from_u8_rgb(200, 200, 200));
+    }
+}
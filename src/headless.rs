@@ -0,0 +1,241 @@
+use std::io;
+use std::path::Path;
+
+use crate::cpu::registers::Registers;
+use crate::cpu::serial::SerialDevice;
+use crate::cpu::CPU;
+use crate::link_cable;
+use crate::scheduler::CYCLES_PER_FRAME;
+
+/// The emulated Game Boy without a window, input or frame pacing. Runs as fast
+/// as it can, which is what test ROM harnesses and tools want.
+pub struct Headless {
+    cpu: CPU,
+    frames: u64,
+    frame_cycles: u32,
+    cycles: u64,
+}
+
+impl Headless {
+    pub fn new() -> Headless {
+        let mut cpu = CPU::new();
+        cpu.bus.serial.capture = true;
+        Headless {
+            cpu,
+            frames: 0,
+            frame_cycles: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Loads a ROM and powers on, boot ROM first like the windowed emulator.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        self.cpu.bus.load_catridge(&path.to_path_buf())?;
+        self.cpu.init_game();
+        self.frames = 0;
+        self.frame_cycles = 0;
+        self.cycles = 0;
+        Ok(())
+    }
+
+    /// Runs one instruction. A frame ends at v-blank, or after a frame's worth
+    /// of cycles with the LCD off.
+    fn step(&mut self) {
+        let vblank = self.cpu.tick();
+        self.frame_cycles += self.cpu.video_cycles();
+        self.cycles += self.cpu.m as u64;
+        if vblank || self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frames += 1;
+            self.frame_cycles = 0;
+        }
+    }
+
+    pub fn run_frame(&mut self) {
+        let frame = self.frames;
+        while self.frames == frame {
+            self.step();
+        }
+    }
+
+    /// Runs until the next instruction is `opcode`, e.g. the `LD B,B` test ROMs use
+    /// as a breakpoint. False if `max_frames` went by first.
+    pub fn run_until_opcode(&mut self, opcode: u8, max_frames: u64) -> bool {
+        while self.frames < max_frames {
+            if !self.cpu.is_halted && self.cpu.bus.peek(self.cpu.pc) == opcode {
+                return true;
+            }
+            self.step();
+        }
+        false
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.cpu.registers
+    }
+
+    /// The last frame as shade numbers 0 (lightest) to 3, one byte per pixel, so it
+    /// can be compared whatever display palette is picked.
+    pub fn screen_shades(&self) -> Vec<u8> {
+        let gpu = &self.cpu.bus.gpu;
+        gpu.buffer
+            .iter()
+            .map(|pixel| gpu.palette.iter().position(|color| color == pixel).unwrap_or(0) as u8)
+            .collect()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.bus.serial.connect(device);
+    }
+
+    /// A byte of memory as the CPU would read it.
+    pub fn peek(&mut self, address: u16) -> u8 {
+        self.cpu.bus.peek(address)
+    }
+
+    /// Bytes sent over the serial port since the last call.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.cpu.bus.serial.output)
+    }
+}
+
+impl Default for Headless {
+    fn default() -> Headless {
+        Headless::new()
+    }
+}
+
+/// Two Game Boys joined by a link cable in the same process. They take turns,
+/// whichever is behind in cycles runs the next instruction, so a run always
+/// gives the same result.
+pub struct LinkedPair {
+    // boxed, two whole machines are too much for a test thread's stack.
+    pub first: Box<Headless>,
+    pub second: Box<Headless>,
+}
+
+impl LinkedPair {
+    pub fn new() -> LinkedPair {
+        let (first_end, second_end) = link_cable::wire();
+        let mut first = Box::new(Headless::new());
+        let mut second = Box::new(Headless::new());
+        first.connect_serial(Box::new(first_end));
+        second.connect_serial(Box::new(second_end));
+        LinkedPair { first, second }
+    }
+
+    /// Loads a ROM into each, both power on together.
+    pub fn load(&mut self, first: &Path, second: &Path) -> io::Result<()> {
+        self.first.load(first)?;
+        self.second.load(second)
+    }
+
+    fn step(&mut self) {
+        if self.first.cycles <= self.second.cycles {
+            self.first.step();
+        } else {
+            self.second.step();
+        }
+    }
+
+    /// Runs until both have finished their current frame.
+    pub fn run_frame(&mut self) {
+        let (first, second) = (self.first.frames, self.second.frames);
+        while self.first.frames == first || self.second.frames == second {
+            self.step();
+        }
+    }
+}
+
+impl Default for LinkedPair {
+    fn default() -> LinkedPair {
+        LinkedPair::new()
+    }
+}
+
+/// Registers as the single-step test vectors describe them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuState {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+}
+
+/// A CPU on 64 KB of plain RAM, for running one instruction at a time.
+pub struct FlatCpu {
+    cpu: CPU,
+}
+
+impl FlatCpu {
+    pub fn new() -> FlatCpu {
+        let mut cpu = CPU::new();
+        cpu.bus.set_flat_ram(true);
+        FlatCpu { cpu }
+    }
+
+    pub fn set_state(&mut self, state: &CpuState) {
+        let r = &mut self.cpu.registers;
+        r.set_af((state.a as u16) << 8 | state.f as u16);
+        r.set_bc((state.b as u16) << 8 | state.c as u16);
+        r.set_de((state.d as u16) << 8 | state.e as u16);
+        r.set_hl((state.h as u16) << 8 | state.l as u16);
+        self.cpu.sp = state.sp;
+        self.cpu.pc = state.pc;
+        self.cpu.bus.interupt_master = state.ime;
+        self.cpu.clear_pending();
+    }
+
+    pub fn state(&self) -> CpuState {
+        let r = &self.cpu.registers;
+        CpuState {
+            a: r.a,
+            b: r.b,
+            c: r.c,
+            d: r.d,
+            e: r.e,
+            f: r.f.con(),
+            h: r.h,
+            l: r.l,
+            sp: self.cpu.sp,
+            pc: self.cpu.pc,
+            ime: self.cpu.bus.interupt_master,
+        }
+    }
+
+    pub fn ram(&mut self) -> &mut [u8] {
+        &mut self.cpu.bus.memory
+    }
+
+    /// Runs one instruction and returns the clock cycles it took.
+    pub fn step(&mut self) -> u32 {
+        self.cpu.bus.access_log.clear();
+        self.cpu.step();
+        self.cpu.m as u32
+    }
+
+    /// (address, value, write) for every memory access of the last `step`.
+    pub fn accesses(&self) -> &[(u16, u8, bool)] {
+        &self.cpu.bus.access_log
+    }
+}
+
+impl Default for FlatCpu {
+    fn default() -> FlatCpu {
+        FlatCpu::new()
+    }
+}
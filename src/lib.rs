@@ -2,18 +2,52 @@
 #![allow(non_snake_case)]
 #![allow(unused_assignments)]
 #![allow(unused_comparisons)]
+pub mod commands;
 mod cpu;
+mod debugger;
+mod gdb_stub;
 mod gpu;
+pub mod headless;
+mod link_cable;
+mod memory_viewer;
+mod movie;
+mod pause_menu;
+pub mod png;
+mod printer;
+mod ram_search;
+mod rewind;
+mod scheduler;
 mod useful_func;
 
-use std::{cmp::Ordering, path::PathBuf, process::exit, time::{Duration, Instant}};
+use std::{fs, io, path::{Path, PathBuf}, process::exit, time::{Duration, Instant}};
 
+pub use cpu::registers::Registers;
+pub use cpu::serial::{SerialDevice, StdoutDevice};
+pub use cpu::trace::TraceStart;
+pub use link_cable::LinkCable;
+pub use printer::Printer;
+use cpu::trace::Tracer;
 use cpu::CPU;
+use debugger::{DebugAction, Debugger};
+use gdb_stub::{GdbAction, GdbStub};
+use gpu::overlay::Overlay;
+use gpu::vram_viewer::{self, VIEWER_HEIGHT, VIEWER_WIDTH};
+use gpu::PALETTES;
+use memory_viewer::MemoryViewer;
+use movie::{Movie, MovieMode, MovieStart};
+use pause_menu::{MenuAction, PauseMenu};
+use rewind::RewindBuffer;
+use scheduler::{FrameScheduler, CYCLES_PER_FRAME};
+use useful_func::*;
 use minifb::{self, Key, KeyRepeat, Menu, ScaleMode};
 use wfd::{self, DialogParams};
 
-const DELTA_INTERVAL: Duration = Duration::from_millis((1000.0 / 59.73) as u64);
-const MAX_CYCLES: u32 = 69905;
+// one snapshot every 2 frames, ~20 seconds of history.
+const REWIND_CAPACITY: usize = 600;
+const REWIND_INTERVAL: u32 = 2;
+// speed multipliers reachable with the +/- hotkeys.
+const SPEED_STEPS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const PRESENT_INTERVAL: Duration = Duration::from_micros(16600);
 
 pub struct Emulator {
     cpu: CPU,
@@ -23,7 +57,26 @@ pub struct Emulator {
     times_renderes: u128,
     pub rom_available : bool,
     initalised_time: Instant,
-    last_frame_time: Instant,
+    scheduler: FrameScheduler,
+    input_state: u8,
+    rom_checksum: u32,
+    movie: MovieMode,
+    rewind: RewindBuffer,
+    speed: f32,
+    turbo: bool,
+    paused: bool,
+    advance_frame: bool,
+    last_present_time: Instant,
+    overlay: Overlay,
+    menu: Option<PauseMenu>,
+    display: Vec<u32>,
+    palette: usize,
+    state_slot: u8,
+    debugger: Debugger,
+    gdb: Option<GdbStub>,
+    vram_window: Option<minifb::Window>,
+    vram_buffer: Vec<u32>,
+    memory_viewer: Option<MemoryViewer>,
 }
 
 
@@ -41,7 +94,8 @@ impl Emulator {
         let mut menu = Menu::new("File").unwrap();
         menu.add_item("Load ROM", 100).shortcut(Key::O, 200).build();
         let mut window = minifb::Window::new("Press \"O\" to Load The GB ROM.", 160, 144, win_opt).unwrap();
-        window.limit_update_rate(Some(Duration::from_micros(16600)));
+        // the frame scheduler does the pacing, minifb sleeping too would double it.
+        window.limit_update_rate(None);
         // let mut current_path = std::env::current_dir().unwrap();
         // current_path.push("retroid.gb");
         Emulator {
@@ -51,8 +105,27 @@ impl Emulator {
             cycles: 0,
             game_rom_path : PathBuf::default(),
             initalised_time: Instant::now(),
-            last_frame_time: Instant::now(),
+            scheduler: FrameScheduler::new(),
             rom_available : false,
+            input_state: 0xFF,
+            rom_checksum: 0,
+            movie: MovieMode::Idle,
+            rewind: RewindBuffer::new(REWIND_CAPACITY, REWIND_INTERVAL),
+            speed: 1.0,
+            turbo: false,
+            paused: false,
+            advance_frame: false,
+            last_present_time: Instant::now(),
+            overlay: Overlay::new(),
+            menu: None,
+            display: vec![0; 160 * 144],
+            palette: 0,
+            state_slot: 0,
+            debugger: Debugger::new(),
+            gdb: None,
+            vram_window: None,
+            vram_buffer: vec![0; VIEWER_WIDTH * VIEWER_HEIGHT],
+            memory_viewer: None,
         }
     }
 
@@ -71,15 +144,43 @@ impl Emulator {
             });
             self.window.update();
         } else {
-            println!(
-                "Rendered Total of {} in {:?}",
-                self.times_renderes,
-                self.initalised_time.elapsed()
-            );
-            exit(0);
+            self.quit();
         }
     }
 
+    /// Saves anything in flight and exits the process.
+    pub fn quit(&mut self) -> ! {
+        self.stop_movie();
+        if let Some(tracer) = &mut self.cpu.tracer {
+            tracer.flush().ok();
+        }
+        println!(
+            "Rendered Total of {} in {:?}",
+            self.times_renderes,
+            self.initalised_time.elapsed()
+        );
+        exit(0);
+    }
+
+    /// Logs every executed instruction to `path`, see `Tracer`.
+    pub fn trace_to(&mut self, path: &Path, start: TraceStart, max_lines: Option<u64>, labels: bool) -> io::Result<()> {
+        let mut tracer = Tracer::create(path, start, max_lines)?;
+        tracer.labels = labels;
+        self.cpu.tracer = Some(tracer);
+        Ok(())
+    }
+
+    /// Plugs a device into the link port, replacing whatever was there.
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.bus.serial.connect(device);
+    }
+
+    /// Waits for GDB on `127.0.0.1:<port>`, see `GdbStub`.
+    pub fn listen_gdb(&mut self, port: u16) -> io::Result<()> {
+        self.gdb = Some(GdbStub::listen(port)?);
+        Ok(())
+    }
+
     pub fn start(&mut self) {
         println!("{:?}",self.game_rom_path);
         match self.cpu.bus.load_catridge(&self.game_rom_path) {
@@ -87,6 +188,184 @@ impl Emulator {
             Err(error) => println!("{}", error),
         }
         self.cpu.init_game();
+        self.rom_checksum = self.cpu.bus.rom_checksum();
+        self.rewind.clear();
+        self.load_cheats();
+        self.load_symbols();
+    }
+
+    /// Loads the RGBDS symbols in `<rom>.sym` if there are any.
+    fn load_symbols(&mut self) {
+        let path = self.game_rom_path.with_extension("sym");
+        self.cpu.symbols.clear();
+        if !path.exists() {
+            return;
+        }
+
+        match self.cpu.symbols.load(&path) {
+            Ok(()) => println!("Loaded {} symbols from {:?}", self.cpu.symbols.len(), path),
+            Err(error) => println!("Could not read {:?} : {}", path, error),
+        }
+    }
+
+    fn cheat_path(&self) -> PathBuf {
+        self.game_rom_path.with_extension("cht")
+    }
+
+    /// Loads `<rom>.cht` if there is one, see `Cheats::load` for the format.
+    fn load_cheats(&mut self) {
+        let path = self.cheat_path();
+        self.cpu.bus.cheats.clear();
+        if !path.exists() {
+            return;
+        }
+
+        match self.cpu.bus.cheats.load(&path) {
+            Ok(()) => println!("Loaded {} cheats from {:?}", self.cpu.bus.cheats.list().len(), path),
+            Err(error) => println!("Could not read {:?} : {}", path, error),
+        }
+    }
+
+    /// Turns a cheat on or off and writes the change back to the cheat file.
+    pub fn toggle_cheat(&mut self, index: usize) {
+        if !matches!(self.movie, MovieMode::Idle) {
+            self.overlay.notify("Cheats can't change during a movie");
+            return;
+        }
+        let path = self.cheat_path();
+        let cheats = &mut self.cpu.bus.cheats;
+        let enabled = match cheats.list().get(index) {
+            Some(cheat) => !cheat.enabled,
+            None => return,
+        };
+        cheats.set_enabled(index, enabled);
+
+        if let Err(error) = cheats.save(&path) {
+            self.overlay.notify(&format!("Could not save cheats : {}", error));
+        }
+    }
+
+    fn cheat_labels(&self) -> Vec<String> {
+        self.cpu
+            .bus
+            .cheats
+            .list()
+            .iter()
+            .map(|cheat| {
+                let name = if cheat.name.is_empty() { &cheat.code } else { &cheat.name };
+                let state = if cheat.enabled { "ON " } else { "OFF" };
+                format!("{} {}", state, name.chars().take(18).collect::<String>())
+            })
+            .collect()
+    }
+
+    fn movie_path(&self) -> PathBuf {
+        self.game_rom_path.with_extension("rbm")
+    }
+
+    /// Starts recording joypad input, either after a fresh power-on or from the current state.
+    pub fn start_recording(&mut self, from_power_on: bool) {
+        self.stop_movie();
+
+        let start = if from_power_on {
+            self.start();
+            MovieStart::PowerOn
+        } else {
+            MovieStart::SaveState(self.cpu.save_state())
+        };
+        let cheats = self.cpu.bus.cheats.enabled_codes();
+        self.movie = MovieMode::Recording(Movie::new(self.rom_checksum, start, cheats));
+        println!("Recording movie to {:?}", self.movie_path());
+    }
+
+    /// Ends recording or playback. A recording is written next to the ROM.
+    pub fn stop_movie(&mut self) {
+        match std::mem::replace(&mut self.movie, MovieMode::Idle) {
+            MovieMode::Recording(movie) => match movie.save(&self.movie_path()) {
+                Ok(()) => println!("Saved movie with {} frames", movie.frames.len()),
+                Err(error) => println!("Could not save movie : {}", error),
+            },
+            MovieMode::Playing(_, frame) => {
+                println!("Stopped movie at frame {}", frame);
+                // back to the cheats in the file, the movie's own were only for playback.
+                self.load_cheats();
+            }
+            MovieMode::Idle => (),
+        }
+    }
+
+    pub fn play_movie(&mut self) -> io::Result<()> {
+        self.stop_movie();
+
+        let movie = Movie::load(&self.movie_path())?;
+        if movie.rom_checksum != self.rom_checksum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "movie was recorded on a different ROM (checksum {:08X}, loaded {:08X})",
+                    movie.rom_checksum, self.rom_checksum
+                ),
+            ));
+        }
+
+        match &movie.start {
+            MovieStart::PowerOn => self.start(),
+            MovieStart::SaveState(state) => self.cpu.load_state(state)?,
+        }
+        // exactly the cheats it was recorded with, or it would desync.
+        self.cpu.bus.cheats.clear();
+        for code in movie.cheats.iter() {
+            if let Err(error) = self.cpu.bus.cheats.add(code, "", true) {
+                self.load_cheats();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+        }
+        println!("Playing movie with {} frames", movie.frames.len());
+        self.movie = MovieMode::Playing(movie, 0);
+        Ok(())
+    }
+
+    /// Picks this frame's joypad state from the movie or the keyboard, and records it if needed.
+    fn next_input(&mut self) -> u8 {
+        let input = match &mut self.movie {
+            MovieMode::Playing(movie, frame) => match movie.frames.get(*frame) {
+                Some(input) => {
+                    *frame += 1;
+                    Some(*input)
+                }
+                None => None,
+            },
+            _ => Some(self.input_state),
+        };
+
+        match input {
+            Some(input) => {
+                if let MovieMode::Recording(movie) = &mut self.movie {
+                    movie.frames.push(input);
+                }
+                input
+            }
+            None => {
+                println!("Movie finished");
+                self.stop_movie();
+                self.input_state
+            }
+        }
+    }
+
+    /// Presses and releases keys so the joypad matches `input`.
+    fn apply_input(&mut self, input: u8) {
+        let current = self.cpu.bus.joypad_state;
+        for key in 0..8 {
+            if test_bit(input, key) == test_bit(current, key) {
+                continue;
+            }
+            if !test_bit(input, key) {
+                self.cpu.get_key_pressed(key);
+            } else {
+                self.cpu.set_key_relased(key);
+            }
+        }
     }
 
     pub fn emulate(&mut self) {
@@ -95,6 +374,12 @@ impl Emulator {
             return;
         }
 
+        if self.menu.is_some() {
+            self.handle_menu();
+            self.present();
+            return;
+        }
+
         self.window.get_keys_pressed(KeyRepeat::No).map(|keys| {
             for t in keys {
                 match t {
@@ -103,6 +388,42 @@ impl Emulator {
 
                     }
 
+                    Key::F1 => match self.movie {
+                        MovieMode::Recording(_) => self.stop_movie(),
+                        _ => self.start_recording(true),
+                    },
+
+                    Key::F2 => match self.movie {
+                        MovieMode::Recording(_) => self.stop_movie(),
+                        _ => self.start_recording(false),
+                    },
+
+                    Key::F3 => match self.movie {
+                        MovieMode::Playing(_, _) => self.stop_movie(),
+                        _ => {
+                            if let Err(error) = self.play_movie() {
+                                println!("Could not play movie : {}", error);
+                            }
+                        }
+                    },
+
+                    Key::P => self.toggle_pause(),
+                    Key::Equal => self.speed_up(),
+                    Key::Minus => self.slow_down(),
+                    Key::Key0 => self.set_speed(1.0),
+                    Key::F4 => self.overlay.show_fps = !self.overlay.show_fps,
+                    Key::Escape => self.open_menu(),
+                    Key::F5 => self.toggle_vram_viewer(),
+                    Key::F6 => match self.dump_vram() {
+                        Ok(path) => self.overlay.notify(&format!("VRAM saved to {}", path.display())),
+                        Err(error) => self.overlay.notify(&format!("VRAM dump failed : {}", error)),
+                    },
+                    Key::F7 => self.toggle_memory_viewer(),
+                    Key::F12 => {
+                        println!("Breaking into the debugger, type help for commands");
+                        self.debugger.break_now();
+                    }
+
                     _ => ()
                 }
             }
@@ -112,37 +433,39 @@ impl Emulator {
             for t in keys {
                 match t {
                     Key::A => {
-                        self.cpu.get_key_pressed(4);
+                        self.input_state = bit_reset(self.input_state, 4);
                     }
                     Key::S => {
                         
-                        self.cpu.get_key_pressed(5);
+                        self.input_state = bit_reset(self.input_state, 5);
                     }
                     Key::Enter => {
                         
-                        self.cpu.get_key_pressed(7);
+                        self.input_state = bit_reset(self.input_state, 7);
                     }
                     Key::Space => {
                         
-                        self.cpu.get_key_pressed(6);
+                        self.input_state = bit_reset(self.input_state, 6);
                     }
 
                     Key::Right => {
                         
-                        self.cpu.get_key_pressed(0);
+                        self.input_state = bit_reset(self.input_state, 0);
                     }
                     Key::Left => {
                         
-                        self.cpu.get_key_pressed(1);
+                        self.input_state = bit_reset(self.input_state, 1);
                     }
                     Key::Up => {
                         
-                        self.cpu.get_key_pressed(2);
+                        self.input_state = bit_reset(self.input_state, 2);
                     }
                     Key::Down => {
                         
-                        self.cpu.get_key_pressed(3);
+                        self.input_state = bit_reset(self.input_state, 3);
                     }
+
+                    Key::N => self.frame_advance(),
                     _ => (),
                 }
             }
@@ -153,73 +476,413 @@ impl Emulator {
                 match t {
                     Key::A => {
         
-                        self.cpu.set_key_relased(4);
+                        self.input_state = bit_set(self.input_state, 4);
                     }
                     Key::S => {
         
-                        self.cpu.set_key_relased(5);
+                        self.input_state = bit_set(self.input_state, 5);
                     }
                     Key::Enter => {
                         
-                        self.cpu.set_key_relased(7);
+                        self.input_state = bit_set(self.input_state, 7);
                     }
                     Key::Space => {
                         
-                        self.cpu.set_key_relased(6);
+                        self.input_state = bit_set(self.input_state, 6);
                     }
 
                     Key::Right => {
                         
-                        self.cpu.set_key_relased(0);
+                        self.input_state = bit_set(self.input_state, 0);
                     }
                     Key::Left => {
                     
-                        self.cpu.set_key_relased(1);
+                        self.input_state = bit_set(self.input_state, 1);
                     }
                     Key::Up => {
             
-                        self.cpu.set_key_relased(2);
+                        self.input_state = bit_set(self.input_state, 2);
                     }
                     Key::Down => {
                     
-                        self.cpu.set_key_relased(3);
+                        self.input_state = bit_set(self.input_state, 3);
                     }
                     _ => (),
                 }
             }
         });
 
-        let this_frame_time = Instant::now();
+        let turbo = self.window.is_key_down(Key::Tab);
+        if turbo != self.turbo {
+            self.set_turbo(turbo);
+        }
+
+        if self.paused {
+            if self.advance_frame {
+                self.advance_frame = false;
+                self.run_frame();
+                self.present();
+            } else {
+                // keep the window and keyboard alive while nothing runs.
+                self.present();
+            }
+            return;
+        }
+
+        if !self.turbo {
+            self.scheduler.wait();
+        }
+
+        if self.window.is_key_down(Key::Backspace) {
+            self.rewind_frame();
+        } else {
+            self.run_frame();
+        }
+    }
+
+    fn run_frame(&mut self) {
+        self.overlay.frame();
+        self.update();
+        if self.rewind.frame_due() {
+            self.rewind.push(self.cpu.save_state());
+        }
+    }
+
+    /// Sets the emulation speed, snapped to the nearest of 0.25x to 8x.
+    /// There is no sound output yet, so nothing has to be pitch corrected.
+    pub fn set_speed(&mut self, multiplier: f32) {
+        let mut nearest = SPEED_STEPS[0];
+        for step in SPEED_STEPS.iter() {
+            if (step - multiplier).abs() < (nearest - multiplier).abs() {
+                nearest = *step;
+            }
+        }
+        self.speed = nearest;
+        self.scheduler.set_speed(nearest);
+        self.update_title();
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn speed_up(&mut self) {
+        if let Some(step) = SPEED_STEPS.iter().find(|step| **step > self.speed) {
+            self.set_speed(*step);
+        }
+    }
+
+    pub fn slow_down(&mut self) {
+        if let Some(step) = SPEED_STEPS.iter().rev().find(|step| **step < self.speed) {
+            self.set_speed(*step);
+        }
+    }
+
+    /// Runs as fast as possible while set, ignoring the speed multiplier.
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.scheduler.reset();
+        self.update_title();
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.update_title();
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.scheduler.reset();
+        self.update_title();
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses if needed and runs exactly one more frame.
+    pub fn frame_advance(&mut self) {
+        if !self.paused {
+            self.pause();
+        }
+        self.advance_frame = true;
+    }
+
+    pub fn open_menu(&mut self) {
+        self.pause();
+        // keys held now won't see their release while the menu has the keyboard.
+        self.input_state = 0xFF;
+        self.menu = Some(PauseMenu::new(self.state_slot, self.palette));
+    }
+
+    pub fn close_menu(&mut self) {
+        self.menu = None;
+        self.resume();
+    }
+
+    fn handle_menu(&mut self) {
+        let keys = self.window.get_keys_pressed(KeyRepeat::No).unwrap_or_default();
+        let mut action = None;
+
+        if let Some(menu) = &mut self.menu {
+            for key in keys {
+                match key {
+                    Key::Up => menu.up(),
+                    Key::Down => menu.down(),
+                    Key::Left => action = menu.change(false),
+                    Key::Right => action = menu.change(true),
+                    Key::Enter => action = Some(menu.select()),
+                    Key::Escape => action = menu.back(),
+                    _ => (),
+                }
+            }
+            self.state_slot = menu.slot;
+        }
+
+        if let Some(action) = action {
+            self.menu_action(action);
+        }
+    }
+
+    fn menu_action(&mut self, action: MenuAction) {
+        match action {
+            MenuAction::Resume => self.close_menu(),
+            MenuAction::Reset => {
+                self.stop_movie();
+                self.start();
+                self.close_menu();
+                self.overlay.notify("Reset");
+            }
+            MenuAction::SaveState(slot) => {
+                match self.save_state_slot(slot) {
+                    Ok(()) => self.overlay.notify(&format!("State {} saved", slot)),
+                    Err(error) => self.overlay.notify(&format!("Save failed : {}", error)),
+                }
+                self.close_menu();
+            }
+            MenuAction::LoadState(slot) => {
+                match self.load_state_slot(slot) {
+                    Ok(()) => self.overlay.notify(&format!("State {} loaded", slot)),
+                    Err(error) => self.overlay.notify(&format!("Load failed : {}", error)),
+                }
+                self.close_menu();
+            }
+            MenuAction::Palette(index) => {
+                self.set_palette(index);
+                self.overlay.notify(&format!("Palette {}", PALETTES[index].0));
+            }
+            MenuAction::Cheats => {
+                let count = self.cpu.bus.cheats.list().len();
+                if count == 0 {
+                    self.overlay.notify(&format!("No cheats in {}", self.cheat_path().display()));
+                }
+                if let Some(menu) = &mut self.menu {
+                    menu.show_cheats(count);
+                }
+            }
+            MenuAction::ToggleCheat(index) => self.toggle_cheat(index),
+            MenuAction::Quit => self.quit(),
+        }
+    }
+
+    pub fn set_palette(&mut self, index: usize) {
+        self.palette = index % PALETTES.len();
+        self.cpu.bus.gpu.palette = PALETTES[self.palette].1;
+    }
+
+    /// Opens or closes the window showing tiles, tile maps and OAM.
+    pub fn toggle_vram_viewer(&mut self) {
+        if self.vram_window.take().is_some() {
+            return;
+        }
+
+        let options = minifb::WindowOptions {
+            resize: true,
+            ..minifb::WindowOptions::default()
+        };
+        match minifb::Window::new("RIP BOY - VRAM", VIEWER_WIDTH, VIEWER_HEIGHT, options) {
+            Ok(window) => self.vram_window = Some(window),
+            Err(error) => println!("Could not open the VRAM viewer : {}", error),
+        }
+    }
+
+    /// Writes the VRAM viewer image to `<rom>.vram.png` and the OAM table to `<rom>.oam.txt`.
+    pub fn dump_vram(&mut self) -> io::Result<PathBuf> {
+        vram_viewer::render(&self.cpu.bus.gpu, &self.cpu.bus.memory, &mut self.vram_buffer);
+        let path = self.game_rom_path.with_extension("vram.png");
+        png::write(&path, VIEWER_WIDTH, VIEWER_HEIGHT, &self.vram_buffer)?;
+
+        let oam = vram_viewer::oam_table(&self.cpu.bus.memory).join("\n");
+        fs::write(self.game_rom_path.with_extension("oam.txt"), oam + "\n")?;
+        Ok(path)
+    }
+
+    fn update_vram_viewer(&mut self) {
+        let open = match &mut self.vram_window {
+            Some(window) if window.is_open() => {
+                vram_viewer::render(&self.cpu.bus.gpu, &self.cpu.bus.memory, &mut self.vram_buffer);
+                window
+                    .update_with_buffer(&self.vram_buffer, VIEWER_WIDTH, VIEWER_HEIGHT)
+                    .is_ok()
+            }
+            _ => false,
+        };
+        if !open {
+            self.vram_window = None;
+        }
+    }
+
+    /// Opens or closes the memory viewer window.
+    pub fn toggle_memory_viewer(&mut self) {
+        if self.memory_viewer.take().is_some() {
+            return;
+        }
+
+        match MemoryViewer::open() {
+            Ok(viewer) => self.memory_viewer = Some(viewer),
+            Err(error) => println!("Could not open the memory viewer : {}", error),
+        }
+    }
+
+    fn update_memory_viewer(&mut self) {
+        let dump_path = self.game_rom_path.with_extension("mem.bin");
+        let open = match &mut self.memory_viewer {
+            Some(viewer) => viewer.update(&mut self.cpu.bus, &dump_path),
+            None => return,
+        };
+        if !open {
+            self.memory_viewer = None;
+        }
+    }
+
+    fn state_path(&self, slot: u8) -> PathBuf {
+        self.game_rom_path.with_extension(format!("ss{}", slot))
+    }
+
+    /// Writes the machine state to slot file `<rom>.ss<slot>`, tagged with the ROM checksum.
+    pub fn save_state_slot(&mut self, slot: u8) -> io::Result<()> {
+        let mut data = self.rom_checksum.to_le_bytes().to_vec();
+        data.extend(self.cpu.save_state());
+        fs::write(self.state_path(slot), data)
+    }
+
+    pub fn load_state_slot(&mut self, slot: u8) -> io::Result<()> {
+        let data = fs::read(self.state_path(slot))?;
+        if data.len() < 4 || data[0..4] != self.rom_checksum.to_le_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "state is from a different ROM",
+            ));
+        }
+
+        // a movie can't follow a jump in time.
+        self.stop_movie();
+        self.cpu.load_state(&data[4..])
+    }
+
+    fn speed_label(&self) -> String {
+        if self.turbo {
+            String::from("TURBO")
+        } else {
+            format!("{}X", self.speed)
+        }
+    }
+
+    fn update_title(&mut self) {
+        let title = if self.paused {
+            String::from("RIP BOY - Paused")
+        } else if self.turbo {
+            String::from("RIP BOY - Turbo")
+        } else if self.speed != 1.0 {
+            format!("RIP BOY - {}x", self.speed)
+        } else {
+            String::from("RIP BOY")
+        };
+        self.window.set_title(&title);
+    }
 
-        if self
-            .last_frame_time
-            .checked_add(DELTA_INTERVAL)
-            .unwrap()
-            .cmp(&this_frame_time)
-            == Ordering::Less
-        {
-            self.update();
-            self.last_frame_time = this_frame_time;
+    /// Steps one snapshot back in time. Rewind is off while a movie is active.
+    pub fn rewind_frame(&mut self) {
+        if let MovieMode::Idle = self.movie {
+            if let Some(state) = self.rewind.pop() {
+                match self.cpu.load_state(&state) {
+                    Ok(()) => {
+                        self.input_state = self.cpu.bus.joypad_state;
+                        self.render();
+                    }
+                    Err(error) => println!("Could not rewind : {}", error),
+                }
+            }
         }
     }
 
+    /// Runs up to the next v-blank. With the LCD off that never comes, so a frame
+    /// is also cut off after `CYCLES_PER_FRAME`. Either way the window gets one update.
     fn update(&mut self) {
         let mut cycles_this_updates = 0;
 
-        while cycles_this_updates <= MAX_CYCLES {
-            self.cpu.step();
-            self.cpu.do_interupts();
-            self.cpu.update_timers(self.cpu.m as u32);
-            if self.cpu.update_graphics(self.cpu.m as i16) {
-                // self._r();
-                self.render();
+        let input = self.next_input();
+        self.apply_input(input);
+        self.cpu.bus.apply_cheats();
+
+        if let Some(gdb) = &mut self.gdb {
+            gdb.poll();
+        }
+
+        while cycles_this_updates < CYCLES_PER_FRAME {
+            if self.debugger.should_break(&mut self.cpu) {
+                self.debug();
+            }
+            let gdb_break = match &mut self.gdb {
+                Some(gdb) => gdb.should_break(&self.cpu),
+                None => false,
+            };
+            if gdb_break {
+                self.serve_gdb();
             }
+            let vblank = self.cpu.tick();
             self.cycles = self.cycles.wrapping_add(self.cpu.m as u128);
-            cycles_this_updates += self.cpu.m as u32;
+            cycles_this_updates += self.cpu.video_cycles();
+            if vblank {
+                break;
+            }
+        }
+        self.render();
+    }
+
+    /// Hands control to the debugger prompt, showing the frame drawn so far.
+    fn debug(&mut self) {
+        self.present();
+        match self.debugger.repl(&mut self.cpu) {
+            DebugAction::Resume => self.scheduler.reset(),
+            DebugAction::Quit => self.quit(),
+        }
+    }
+
+    /// Leaves the CPU to GDB until it resumes.
+    fn serve_gdb(&mut self) {
+        self.present();
+        let action = match &mut self.gdb {
+            Some(gdb) => gdb.serve(&mut self.cpu),
+            None => GdbAction::Resume,
+        };
+        match action {
+            GdbAction::Resume => self.scheduler.reset(),
+            GdbAction::Quit => self.quit(),
         }
     }
 
     fn get_game_rom(&mut self) {
+        self.stop_movie();
+
         let params = DialogParams {
             default_extension : ".gb",
             ok_button_label : "Emulate",
@@ -239,17 +902,33 @@ impl Emulator {
     pub fn render(&mut self) {
         self.times_renderes += 1;
 
+        // faster than real time : only show as many frames as the screen refreshes.
+        if (self.turbo || self.speed > 1.0) && self.last_present_time.elapsed() < PRESENT_INTERVAL {
+            return;
+        }
+        self.present();
+    }
+
+    fn present(&mut self) {
+        self.last_present_time = Instant::now();
+
+        // the overlay goes on a copy so it never ends up in the emulated frame.
+        self.display.copy_from_slice(&self.cpu.bus.gpu.buffer);
+        let speed = self.speed_label();
+        self.overlay.draw(&mut self.display, 160, &speed);
+        if let Some(menu) = &self.menu {
+            let cheats = self.cheat_labels();
+            menu.draw(&mut self.display, 160, &cheats);
+        }
+        self.update_vram_viewer();
+        self.update_memory_viewer();
+
         if self.window.is_open() {
             self.window
-                .update_with_buffer(&self.cpu.bus.gpu.buffer, 160, 144)
+                .update_with_buffer(&self.display, 160, 144)
                 .unwrap();
         } else {
-            println!(
-                "Rendered Total of {} in {:?}",
-                self.times_renderes,
-                self.initalised_time.elapsed()
-            );
-            exit(0);
+            self.quit();
         }
     }
 }
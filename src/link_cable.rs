@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::cpu::serial::SerialDevice;
+
+// messages are three bytes : a kind, the number of the transfer and a data byte.
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+// how long a transfer waits for the other side before reading 0xFF.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+fn timed_out(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// A link cable to another emulator over a local socket. Transfers are lockstep :
+/// the side whose clock runs sends `TRANSFER` with its byte and waits for the
+/// `REPLY` carrying the byte the other side had in SB. Replies carry the number
+/// of the transfer they answer, so one that comes after we gave up waiting is
+/// told apart from the reply to the next transfer.
+pub struct LinkCable {
+    stream: Option<Stream>,
+    // number of our last transfer.
+    sequence: u8,
+}
+
+impl LinkCable {
+    /// Waits for the other emulator. An address with a `/` is a Unix socket path,
+    /// a bare number is a port on 127.0.0.1, anything else is `host:port`.
+    pub fn listen(address: &str) -> io::Result<LinkCable> {
+        println!("Waiting for the other Game Boy on {}", address);
+        #[cfg(unix)]
+        {
+            if address.contains('/') {
+                let (stream, _) = UnixListener::bind(address)?.accept()?;
+                return LinkCable::open(Stream::Unix(stream));
+            }
+        }
+
+        let listener = match address.parse::<u16>() {
+            Ok(port) => TcpListener::bind(("127.0.0.1", port))?,
+            Err(_) => TcpListener::bind(address)?,
+        };
+        let (stream, peer) = listener.accept()?;
+        println!("Link cable connected to {}", peer);
+        stream.set_nodelay(true)?;
+        LinkCable::open(Stream::Tcp(stream))
+    }
+
+    pub fn connect(address: &str) -> io::Result<LinkCable> {
+        #[cfg(unix)]
+        {
+            if address.contains('/') {
+                return LinkCable::open(Stream::Unix(UnixStream::connect(address)?));
+            }
+        }
+
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        println!("Link cable connected to {}", address);
+        LinkCable::open(Stream::Tcp(stream))
+    }
+
+    fn open(stream: Stream) -> io::Result<LinkCable> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(LinkCable {
+            stream: Some(stream),
+            sequence: 0,
+        })
+    }
+
+    fn disconnect(&mut self, error: io::Error) {
+        if self.stream.take().is_some() {
+            println!("Link cable disconnected : {}", error);
+        }
+    }
+
+    fn send(&mut self, kind: u8, sequence: u8, byte: u8) {
+        let sent = match &mut self.stream {
+            Some(stream) => stream.write_all(&[kind, sequence, byte]),
+            None => Ok(()),
+        };
+        if let Err(error) = sent {
+            self.disconnect(error);
+        }
+    }
+
+    /// One message, waiting up to `TIMEOUT` for it or not at all.
+    fn receive(&mut self, wait: bool) -> io::Result<Option<(u8, u8, u8)>> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+
+        let mut message = [0; 3];
+        stream.set_nonblocking(!wait)?;
+        let first = stream.read(&mut message[..1]);
+        stream.set_nonblocking(false)?;
+        match first {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => (),
+            Err(error) if timed_out(&error) => return Ok(None),
+            Err(error) => return Err(error),
+        }
+        // the rest is on its way, wait for it.
+        stream.read_exact(&mut message[1..])?;
+        Ok(Some((message[0], message[1], message[2])))
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.send(TRANSFER, self.sequence, sent);
+
+        while self.stream.is_some() {
+            match self.receive(true) {
+                Ok(Some((REPLY, sequence, received))) if sequence == self.sequence => return received,
+                // both clocks ran at once : each side takes the other's byte.
+                Ok(Some((TRANSFER, sequence, received))) => {
+                    self.send(REPLY, sequence, sent);
+                    return received;
+                }
+                // a late reply to a transfer that already read 0xFF.
+                Ok(Some(_)) => (),
+                Ok(None) => break,
+                Err(error) => self.disconnect(error),
+            }
+        }
+        0xFF
+    }
+
+    fn poll_external(&mut self, sent: Option<u8>) -> Option<u8> {
+        match self.receive(false) {
+            Ok(Some((TRANSFER, sequence, received))) => {
+                // not waiting on a transfer, so nothing was shifted out.
+                self.send(REPLY, sequence, sent.unwrap_or(0xFF));
+                sent.map(|_| received)
+            }
+            // a reply that came after a clash or a timeout, already dealt with.
+            Ok(_) => None,
+            Err(error) => {
+                self.disconnect(error);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Wire {
+    // what each side has in SB while it waits on the external clock.
+    waiting: [Option<u8>; 2],
+    // bytes the other side's clock shifted in, not picked up yet.
+    incoming: [Option<u8>; 2],
+}
+
+/// One end of a link cable between two cores in the same process, see `wire`.
+pub struct WireEnd {
+    side: usize,
+    wire: Rc<RefCell<Wire>>,
+}
+
+/// Both ends of a link cable that needs no sockets, for running two cores side
+/// by side. Exchanges happen in whatever order the cores are stepped, so a run
+/// always comes out the same.
+pub fn wire() -> (WireEnd, WireEnd) {
+    let wire = Rc::new(RefCell::new(Wire::default()));
+    (
+        WireEnd {
+            side: 0,
+            wire: wire.clone(),
+        },
+        WireEnd { side: 1, wire },
+    )
+}
+
+impl SerialDevice for WireEnd {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        let other = 1 - self.side;
+        let mut wire = self.wire.borrow_mut();
+        match wire.waiting[other].take() {
+            Some(received) => {
+                wire.incoming[other] = Some(sent);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll_external(&mut self, sent: Option<u8>) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let received = wire.incoming[self.side].take();
+        wire.waiting[self.side] = if received.is_some() { None } else { sent };
+        received
+    }
+}
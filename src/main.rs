@@ -1,7 +1,22 @@
-use emulator::{self, Emulator};
+use std::{env, process::exit};
+
+use emulator::{self, commands, Emulator};
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "disasm" {
+        if let Err(error) = commands::disasm(&args[2..]) {
+            eprintln!("{}", error);
+            exit(1);
+        }
+        return;
+    }
+
     let mut emu = Emulator::new();
+    if let Err(error) = commands::configure(&mut emu, &args[1..]) {
+        eprintln!("{}", error);
+        exit(1);
+    }
     println!("Press \"O\" to Select a ROM to load. ");
     while !emu.rom_available {
         emu.open();
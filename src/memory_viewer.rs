@@ -0,0 +1,357 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cpu::memory_bus::MemoryBus;
+use crate::cpu::memory_map::*;
+use crate::gpu::overlay::{draw_text, fill_rect, CHAR_ADVANCE, LINE_HEIGHT};
+use crate::useful_func::*;
+use minifb::{Key, KeyRepeat};
+
+const BYTES_PER_ROW: u32 = 16;
+const ROWS: u32 = 32;
+// columns, in characters, of each part of a row.
+const REGION_COLUMN: usize = 5;
+const HEX_COLUMN: usize = 10;
+const ASCII_COLUMN: usize = HEX_COLUMN + 16 * 3 + 1;
+// a changed byte stays highlighted for about half a second.
+const CHANGE_HIGHLIGHT_FRAMES: u32 = 30;
+
+pub const VIEWER_WIDTH: usize = (ASCII_COLUMN + 16) * CHAR_ADVANCE + 4;
+pub const VIEWER_HEIGHT: usize = (ROWS as usize + 3) * LINE_HEIGHT + 4;
+
+const HELP: &str = "ARROWS PGUP PGDN MOVE  0-F EDIT  / FIND  N NEXT  G GOTO  S DUMP";
+
+/// Reads a byte the way the CPU sees it. The unusable 0xFEA0-0xFEFF has no value.
+fn read(bus: &mut MemoryBus, address: u16) -> Option<u8> {
+    region_name(address as usize).map(|_| bus.peek(address))
+}
+
+/// Bytes of a region for dumping to a file :
+/// `all` the whole 64 KB address space, `ROM0` `VRAM` `WRAM` ... a region from
+/// `memory_map.rs` as currently mapped, `rom` the whole cartridge, `rom<n>` one ROM
+/// bank, `ram` all cartridge RAM, `ram<n>` one RAM bank, or `<start>-<end>` in hex.
+pub fn region_bytes(bus: &mut MemoryBus, spec: &str) -> Result<Vec<u8>, String> {
+    let lower = spec.to_ascii_lowercase();
+    let range = |bus: &mut MemoryBus, start: usize, end: usize| -> Vec<u8> {
+        (start..=end).map(|a| read(bus, a as u16).unwrap_or(0xFF)).collect()
+    };
+
+    if lower == "all" {
+        return Ok(range(bus, 0x0000, 0xFFFF));
+    }
+    if lower == "rom" {
+        let banks = bus.rom_bank_count();
+        return Ok((0..banks).flat_map(|bank| bus.rom_bank(bank).unwrap_or(&[]).to_vec()).collect());
+    }
+    if lower == "ram" {
+        return Ok((0..4).flat_map(|bank| bus.ram_bank(bank).unwrap_or(&[]).to_vec()).collect());
+    }
+    if let Some(bank) = lower.strip_prefix("rom").and_then(|n| n.parse::<usize>().ok()) {
+        if bank >= bus.rom_bank_count() {
+            return Err(format!("the ROM has {} banks", bus.rom_bank_count()));
+        }
+        return bus.rom_bank(bank).map(|b| b.to_vec()).ok_or_else(|| String::from("no such bank"));
+    }
+    if let Some(bank) = lower.strip_prefix("ram").and_then(|n| n.parse::<usize>().ok()) {
+        return bus.ram_bank(bank).map(|b| b.to_vec()).ok_or_else(|| String::from("no such RAM bank"));
+    }
+    if let Some((_, begin, end)) = REGIONS.iter().find(|(name, _, _)| name.eq_ignore_ascii_case(spec)) {
+        return Ok(range(bus, *begin, *end));
+    }
+    if let Some((start, end)) = spec.split_once('-') {
+        if let (Some(start), Some(end)) = (parse_hex(start), parse_hex(end)) {
+            if start <= end {
+                return Ok(range(bus, start as usize, end as usize));
+            }
+        }
+    }
+    Err(format!("unknown region {:?}", spec))
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    match key {
+        Key::Key0 | Key::NumPad0 => Some(0x0),
+        Key::Key1 | Key::NumPad1 => Some(0x1),
+        Key::Key2 | Key::NumPad2 => Some(0x2),
+        Key::Key3 | Key::NumPad3 => Some(0x3),
+        Key::Key4 | Key::NumPad4 => Some(0x4),
+        Key::Key5 | Key::NumPad5 => Some(0x5),
+        Key::Key6 | Key::NumPad6 => Some(0x6),
+        Key::Key7 | Key::NumPad7 => Some(0x7),
+        Key::Key8 | Key::NumPad8 => Some(0x8),
+        Key::Key9 | Key::NumPad9 => Some(0x9),
+        Key::A => Some(0xA),
+        Key::B => Some(0xB),
+        Key::C => Some(0xC),
+        Key::D => Some(0xD),
+        Key::E => Some(0xE),
+        Key::F => Some(0xF),
+        _ => None,
+    }
+}
+
+/// What typed hex digits currently go to.
+enum Input {
+    Edit(Option<u8>),
+    Find(String),
+    Goto(String),
+}
+
+/// Window showing the whole address space, 16 bytes a row.
+pub struct MemoryViewer {
+    window: minifb::Window,
+    buffer: Vec<u32>,
+    top: u32,
+    cursor: u32,
+    input: Input,
+    pattern: Vec<u8>,
+    // value each byte had when last drawn, None until it has been on screen.
+    last: Vec<Option<u8>>,
+    changed_at: Vec<u32>,
+    frame: u32,
+    status: String,
+}
+
+impl MemoryViewer {
+    pub fn open() -> minifb::Result<MemoryViewer> {
+        let options = minifb::WindowOptions {
+            resize: true,
+            ..minifb::WindowOptions::default()
+        };
+        let window = minifb::Window::new("RIP BOY - Memory", VIEWER_WIDTH, VIEWER_HEIGHT, options)?;
+
+        Ok(MemoryViewer {
+            window,
+            buffer: vec![0; VIEWER_WIDTH * VIEWER_HEIGHT],
+            top: 0,
+            cursor: 0,
+            input: Input::Edit(None),
+            pattern: Vec::new(),
+            last: vec![None; 0x10000],
+            changed_at: vec![0; 0x10000],
+            frame: 0,
+            status: String::new(),
+        })
+    }
+
+    /// Handles keys and redraws. Returns false once the window was closed.
+    /// `S` writes the whole address space to `dump_path`.
+    pub fn update(&mut self, bus: &mut MemoryBus, dump_path: &Path) -> bool {
+        if !self.window.is_open() {
+            return false;
+        }
+
+        let keys = self.window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default();
+        for key in keys {
+            self.handle_key(key, bus, dump_path);
+        }
+
+        self.frame = self.frame.wrapping_add(1);
+        self.draw(bus);
+        self.window
+            .update_with_buffer(&self.buffer, VIEWER_WIDTH, VIEWER_HEIGHT)
+            .is_ok()
+    }
+
+    fn move_cursor(&mut self, offset: i32) {
+        self.cursor = (self.cursor as i32 + offset).clamp(0, 0xFFFF) as u32;
+        self.input = Input::Edit(None);
+
+        let row = self.cursor - self.cursor % BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + ROWS * BYTES_PER_ROW {
+            self.top = row + BYTES_PER_ROW - ROWS * BYTES_PER_ROW;
+        }
+    }
+
+    fn go_to(&mut self, address: u16) {
+        self.move_cursor(address as i32 - self.cursor as i32);
+    }
+
+    fn handle_key(&mut self, key: Key, bus: &mut MemoryBus, dump_path: &Path) {
+        let digit = hex_digit(key);
+
+        match &mut self.input {
+            Input::Find(text) | Input::Goto(text) => {
+                match key {
+                    Key::Enter => self.finish_input(bus),
+                    Key::Escape => self.input = Input::Edit(None),
+                    Key::Backspace => {
+                        text.pop();
+                    }
+                    _ => {
+                        if let Some(digit) = digit {
+                            text.push_str(&format!("{:X}", digit));
+                        }
+                    }
+                }
+                return;
+            }
+            Input::Edit(high) => {
+                if let Some(digit) = digit {
+                    match *high {
+                        None => *high = Some(digit),
+                        Some(high) => {
+                            self.write(bus, high << 4 | digit);
+                            self.move_cursor(1);
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+
+        match key {
+            Key::Left => self.move_cursor(-1),
+            Key::Right => self.move_cursor(1),
+            Key::Up => self.move_cursor(-(BYTES_PER_ROW as i32)),
+            Key::Down => self.move_cursor(BYTES_PER_ROW as i32),
+            Key::PageUp => self.move_cursor(-((ROWS * BYTES_PER_ROW) as i32)),
+            Key::PageDown => self.move_cursor((ROWS * BYTES_PER_ROW) as i32),
+            Key::Home => self.go_to(0x0000),
+            Key::End => self.go_to(0xFFFF),
+            Key::Escape => self.input = Input::Edit(None),
+            Key::Slash => self.input = Input::Find(String::new()),
+            Key::G => self.input = Input::Goto(String::new()),
+            Key::N => self.find_next(bus),
+            Key::S => {
+                self.status = match region_bytes(bus, "all").map(|data| fs::write(dump_path, data)) {
+                    Ok(Ok(())) => format!("DUMPED TO {}", dump_path.display()),
+                    Ok(Err(error)) => format!("DUMP FAILED : {}", error),
+                    Err(error) => error,
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn write(&mut self, bus: &mut MemoryBus, value: u8) {
+        let address = self.cursor as usize;
+        // writes to ROM switch banks rather than change bytes, so they are left out.
+        if address <= ROM_1_END || region_name(address).is_none() {
+            self.status = format!("{:04X} IS NOT WRITABLE", address);
+            return;
+        }
+        bus.poke(address as u16, value);
+        self.status = format!("{:04X} = {:02X}", address, value);
+    }
+
+    fn finish_input(&mut self, bus: &mut MemoryBus) {
+        match std::mem::replace(&mut self.input, Input::Edit(None)) {
+            Input::Goto(text) => match parse_hex(&text) {
+                Some(address) => self.go_to(address),
+                None => self.status = String::from("BAD ADDRESS"),
+            },
+            Input::Find(text) => {
+                // an odd digit out is taken as the low nibble of one more byte.
+                let text = if text.len() % 2 == 1 { format!("0{}", text) } else { text };
+                self.pattern = (0..text.len())
+                    .step_by(2)
+                    .filter_map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+                    .collect();
+                self.find_next(bus);
+            }
+            Input::Edit(_) => (),
+        }
+    }
+
+    /// Moves the cursor to the next match of the search pattern, wrapping around.
+    fn find_next(&mut self, bus: &mut MemoryBus) {
+        if self.pattern.is_empty() {
+            self.status = String::from("NOTHING TO FIND, PRESS /");
+            return;
+        }
+
+        for offset in 1..=0x10000u32 {
+            let start = (self.cursor + offset) % 0x10000;
+            let found = self.pattern.iter().enumerate().all(|(i, byte)| {
+                read(bus, (start as usize + i) as u16) == Some(*byte)
+            });
+            if found {
+                self.go_to(start as u16);
+                self.status = format!("FOUND AT {:04X}", start);
+                return;
+            }
+        }
+        self.status = String::from("NOT FOUND");
+    }
+
+    fn draw(&mut self, bus: &mut MemoryBus) {
+        let white = from_u8_rgb(255, 255, 255);
+        let grey = from_u8_rgb(140, 140, 140);
+        let changed = from_u8_rgb(255, 220, 0);
+        let width = VIEWER_WIDTH;
+        let text_x = |column: usize| 2 + column * CHAR_ADVANCE;
+
+        fill_rect(&mut self.buffer, width, 0, 0, width, VIEWER_HEIGHT, from_u8_rgb(16, 16, 24));
+
+        let header = match &self.input {
+            Input::Find(text) => format!("FIND : {}_", text),
+            Input::Goto(text) => format!("GOTO : {}_", text),
+            Input::Edit(_) => format!(
+                "{:04X} {}  ROM BANK {}  RAM BANK {}  {}",
+                self.cursor,
+                region_name(self.cursor as usize).unwrap_or("----"),
+                bus.current_rom_bank(),
+                bus.current_ram_bank(),
+                self.status
+            ),
+        };
+        draw_text(&mut self.buffer, width, text_x(0), 2, &header, white);
+
+        for row in 0..ROWS {
+            let row_address = self.top + row * BYTES_PER_ROW;
+            if row_address > 0xFFFF {
+                break;
+            }
+            let y = 2 + (row as usize + 1) * LINE_HEIGHT;
+
+            draw_text(&mut self.buffer, width, text_x(0), y, &format!("{:04X}", row_address), grey);
+            let region = region_name(row_address as usize).unwrap_or("----");
+            draw_text(&mut self.buffer, width, text_x(REGION_COLUMN), y, region, grey);
+
+            for i in 0..BYTES_PER_ROW {
+                let address = (row_address + i) as usize;
+                let column = HEX_COLUMN + i as usize * 3 + if i >= 8 { 1 } else { 0 };
+
+                let value = match read(bus, address as u16) {
+                    Some(value) => value,
+                    None => {
+                        draw_text(&mut self.buffer, width, text_x(column), y, "--", grey);
+                        continue;
+                    }
+                };
+
+                if let Some(last) = self.last[address] {
+                    if last != value {
+                        self.changed_at[address] = self.frame;
+                    }
+                } else {
+                    // first time on screen, nothing to compare with.
+                    self.changed_at[address] = self.frame.wrapping_sub(CHANGE_HIGHLIGHT_FRAMES);
+                }
+                self.last[address] = Some(value);
+                let recent = self.frame.wrapping_sub(self.changed_at[address]) < CHANGE_HIGHLIGHT_FRAMES;
+                let color = if recent { changed } else { white };
+
+                if address as u32 == self.cursor {
+                    let cursor = from_u8_rgb(40, 80, 200);
+                    let (x, w) = (text_x(column) - 1, 2 * CHAR_ADVANCE + 1);
+                    fill_rect(&mut self.buffer, width, x, y - 1, w, LINE_HEIGHT, cursor);
+                }
+                let text = match (&self.input, address as u32 == self.cursor) {
+                    (Input::Edit(Some(high)), true) => format!("{:X}_", high),
+                    _ => format!("{:02X}", value),
+                };
+                draw_text(&mut self.buffer, width, text_x(column), y, &text, color);
+
+                let c = if (0x20..0x7F).contains(&value) { value as char } else { '.' };
+                draw_text(&mut self.buffer, width, text_x(ASCII_COLUMN + i as usize), y, &c.to_string(), grey);
+            }
+        }
+
+        let y = 2 + (ROWS as usize + 2) * LINE_HEIGHT;
+        draw_text(&mut self.buffer, width, text_x(0), y, HELP, grey);
+    }
+}
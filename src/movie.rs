@@ -0,0 +1,110 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cpu::state::{StateReader, StateWriter};
+
+const MOVIE_MAGIC: &[u8; 4] = b"RBMV";
+const MOVIE_VERSION: u8 = 2;
+
+/// Where the recorded input starts from.
+pub enum MovieStart {
+    PowerOn,
+    SaveState(Vec<u8>),
+}
+
+/// Joypad state per frame, one byte each in the same layout as `MemoryBus::joypad_state`.
+/// The cheats that were on are kept too, a movie only replays with the same ones.
+pub struct Movie {
+    pub rom_checksum: u32,
+    pub start: MovieStart,
+    pub cheats: Vec<String>,
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(rom_checksum: u32, start: MovieStart, cheats: Vec<String>) -> Movie {
+        Movie {
+            rom_checksum,
+            start,
+            cheats,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut w = StateWriter::new();
+        w.bytes(MOVIE_MAGIC);
+        w.u8(MOVIE_VERSION);
+        w.u32(self.rom_checksum);
+        match &self.start {
+            MovieStart::PowerOn => w.u8(0),
+            MovieStart::SaveState(state) => {
+                w.u8(1);
+                w.u32(state.len() as u32);
+                w.bytes(state);
+            }
+        }
+        w.u16(self.cheats.len() as u16);
+        for code in self.cheats.iter() {
+            w.u8(code.len() as u8);
+            w.bytes(code.as_bytes());
+        }
+        w.u32(self.frames.len() as u32);
+        w.bytes(&self.frames);
+        fs::write(path, w.data)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Movie> {
+        let data = fs::read(path)?;
+        let mut r = StateReader::new(&data);
+
+        let mut magic = [0u8; 4];
+        r.bytes(&mut magic)?;
+        if &magic != MOVIE_MAGIC || r.u8()? != MOVIE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a RIP Boy movie",
+            ));
+        }
+
+        let rom_checksum = r.u32()?;
+        let start = match r.u8()? {
+            0 => MovieStart::PowerOn,
+            1 => {
+                let len = r.u32()? as usize;
+                MovieStart::SaveState(r.vec(len)?)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unknown movie start point",
+                ))
+            }
+        };
+
+        let mut cheats = Vec::new();
+        for _ in 0..r.u16()? {
+            let len = r.u8()? as usize;
+            let code = String::from_utf8(r.vec(len)?)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad cheat code in movie"))?;
+            cheats.push(code);
+        }
+
+        let len = r.u32()? as usize;
+        let frames = r.vec(len)?;
+
+        Ok(Movie {
+            rom_checksum,
+            start,
+            cheats,
+            frames,
+        })
+    }
+}
+
+pub enum MovieMode {
+    Idle,
+    Recording(Movie),
+    Playing(Movie, usize),
+}
@@ -0,0 +1,137 @@
+use crate::gpu::overlay::draw_menu;
+use crate::gpu::PALETTES;
+
+pub const STATE_SLOTS: u8 = 10;
+// cheat rows shown at once, the page scrolls with the selection.
+const CHEAT_ROWS: usize = 8;
+
+pub enum MenuAction {
+    Resume,
+    Reset,
+    SaveState(u8),
+    LoadState(u8),
+    Palette(usize),
+    Cheats,
+    ToggleCheat(usize),
+    Quit,
+}
+
+const ITEMS: [&str; 7] = ["Resume", "Reset", "Save State", "Load State", "Palette", "Cheats", "Quit"];
+const SAVE_STATE: usize = 2;
+const LOAD_STATE: usize = 3;
+const PALETTE: usize = 4;
+const CHEATS: usize = 5;
+
+/// Menu shown while the game is paused. Left/Right change the state slot and palette.
+/// The cheat page lists the ROM's cheats, Enter turns the selected one on or off.
+pub struct PauseMenu {
+    selected: usize,
+    pub slot: u8,
+    pub palette: usize,
+    // selected row and number of cheats while the cheat page is open.
+    cheat_page: Option<(usize, usize)>,
+}
+
+impl PauseMenu {
+    pub fn new(slot: u8, palette: usize) -> PauseMenu {
+        PauseMenu {
+            selected: 0,
+            slot,
+            palette,
+            cheat_page: None,
+        }
+    }
+
+    pub fn show_cheats(&mut self, count: usize) {
+        if count > 0 {
+            self.cheat_page = Some((0, count));
+        }
+    }
+
+    /// Leaves the cheat page, or resumes from the main page.
+    pub fn back(&mut self) -> Option<MenuAction> {
+        match self.cheat_page.take() {
+            Some(_) => None,
+            None => Some(MenuAction::Resume),
+        }
+    }
+
+    pub fn up(&mut self) {
+        match &mut self.cheat_page {
+            Some((row, count)) => *row = (*row + *count - 1) % *count,
+            None => self.selected = (self.selected + ITEMS.len() - 1) % ITEMS.len(),
+        }
+    }
+
+    pub fn down(&mut self) {
+        match &mut self.cheat_page {
+            Some((row, count)) => *row = (*row + 1) % *count,
+            None => self.selected = (self.selected + 1) % ITEMS.len(),
+        }
+    }
+
+    /// Cycles the value on the selected row. Palettes apply right away.
+    pub fn change(&mut self, forward: bool) -> Option<MenuAction> {
+        if self.cheat_page.is_some() {
+            return None;
+        }
+
+        match self.selected {
+            SAVE_STATE | LOAD_STATE => {
+                self.slot = if forward {
+                    (self.slot + 1) % STATE_SLOTS
+                } else {
+                    (self.slot + STATE_SLOTS - 1) % STATE_SLOTS
+                };
+                None
+            }
+            PALETTE => {
+                self.palette = if forward {
+                    (self.palette + 1) % PALETTES.len()
+                } else {
+                    (self.palette + PALETTES.len() - 1) % PALETTES.len()
+                };
+                Some(MenuAction::Palette(self.palette))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn select(&self) -> MenuAction {
+        if let Some((row, _)) = self.cheat_page {
+            return MenuAction::ToggleCheat(row);
+        }
+
+        match self.selected {
+            0 => MenuAction::Resume,
+            1 => MenuAction::Reset,
+            SAVE_STATE => MenuAction::SaveState(self.slot),
+            LOAD_STATE => MenuAction::LoadState(self.slot),
+            PALETTE => MenuAction::Palette(self.palette),
+            CHEATS => MenuAction::Cheats,
+            _ => MenuAction::Quit,
+        }
+    }
+
+    /// `cheats` holds one label per cheat, only used on the cheat page.
+    pub fn draw(&self, buffer: &mut [u32], width: usize, cheats: &[String]) {
+        if let Some((row, _)) = self.cheat_page {
+            let first = row.saturating_sub(CHEAT_ROWS - 1);
+            let end = (first + CHEAT_ROWS).min(cheats.len());
+            draw_menu(buffer, width, "CHEATS", &cheats[first..end], row - first);
+            return;
+        }
+
+        let items: Vec<String> = ITEMS
+            .iter()
+            .enumerate()
+            .map(|(i, item)| match i {
+                SAVE_STATE | LOAD_STATE => format!("{} <{}>", item, self.slot),
+                PALETTE => format!("{} <{}>", item, PALETTES[self.palette].0),
+                _ => item.to_string(),
+            })
+            .collect();
+
+        draw_menu(buffer, width, "PAUSED", &items, self.selected);
+    }
+}
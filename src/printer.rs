@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+
+use crate::cpu::serial::SerialDevice;
+use crate::png;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// status bits.
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const UNPRINTED_DATA: u8 = 0x08;
+
+// the printer answers the byte after the checksum with this, so games know it's there.
+const ALIVE: u8 = 0x81;
+// paper is 160 pixels wide, 20 tiles of 16 bytes.
+const WIDTH: usize = 160;
+const TILE_ROW_BYTES: usize = 20 * 16;
+// status requests answered with the printing bit after a print.
+const PRINTING_POLLS: u8 = 4;
+const SHADES: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+
+/// Where the printer is in a packet : `88 33`, command, compression, length
+/// (little endian), data, checksum (little endian), then two bytes the printer
+/// answers with `ALIVE` and its status.
+#[derive(Clone, Copy)]
+enum State {
+    Magic,
+    MagicSecond,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// The Game Boy Printer. Every print command writes the image received since
+/// the last one to the next free `print_<n>.png` in a directory.
+pub struct Printer {
+    dir: PathBuf,
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    expected_checksum: u16,
+    // 2bpp tile data waiting to be printed.
+    image: Vec<u8>,
+    status: u8,
+    printing_polls: u8,
+    pages: Vec<PathBuf>,
+}
+
+impl Printer {
+    pub fn new(dir: &Path) -> Printer {
+        Printer {
+            dir: dir.to_path_buf(),
+            state: State::Magic,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            expected_checksum: 0,
+            image: Vec::new(),
+            status: 0,
+            printing_polls: 0,
+            pages: Vec::new(),
+        }
+    }
+
+    /// The images written so far.
+    pub fn pages(&self) -> &[PathBuf] {
+        &self.pages
+    }
+
+    fn run_command(&mut self) {
+        if self.checksum != self.expected_checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            }
+            // an empty data packet just marks the end of the image.
+            DATA if self.compressed => {
+                let data = decompress(&self.data);
+                self.image.extend_from_slice(&data);
+            }
+            DATA => self.image.extend_from_slice(&self.data),
+            PRINT => {
+                // sheets, margins, palette, exposure.
+                let palette = self.data.get(2).copied().unwrap_or(0);
+                self.print(palette);
+                self.image.clear();
+                self.status |= PRINTING;
+                self.printing_polls = PRINTING_POLLS;
+            }
+            STATUS if self.printing_polls > 0 => {
+                self.printing_polls -= 1;
+                if self.printing_polls == 0 {
+                    self.status &= !PRINTING;
+                }
+            }
+            _ => (),
+        }
+
+        if self.image.is_empty() {
+            self.status &= !UNPRINTED_DATA;
+        } else {
+            self.status |= UNPRINTED_DATA;
+        }
+    }
+
+    fn print(&mut self, palette: u8) {
+        let rows = self.image.len() / TILE_ROW_BYTES;
+        if rows == 0 {
+            return;
+        }
+        // 0 means the usual palette.
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        let height = rows * 8;
+        let mut pixels = vec![0; WIDTH * height];
+        for (row, tiles) in self.image.chunks_exact(TILE_ROW_BYTES).enumerate() {
+            for (column, tile) in tiles.chunks_exact(16).enumerate() {
+                for y in 0..8 {
+                    let (low, high) = (tile[y * 2], tile[y * 2 + 1]);
+                    for x in 0..8 {
+                        let bit = 7 - x;
+                        let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                        let shade = (palette >> (color * 2)) & 3;
+                        pixels[(row * 8 + y) * WIDTH + column * 8 + x] = SHADES[shade as usize];
+                    }
+                }
+            }
+        }
+
+        let path = (1..)
+            .map(|n| self.dir.join(format!("print_{}.png", n)))
+            .find(|path| !path.exists())
+            .unwrap();
+        match png::write(&path, WIDTH, height, &pixels) {
+            Ok(()) => {
+                println!("Printed {:?}", path);
+                self.pages.push(path);
+            }
+            Err(error) => println!("Could not write {:?} : {}", path, error),
+        }
+    }
+}
+
+/// Run length encoding : a control byte with bit 7 set repeats the next byte
+/// (control & 0x7F) + 2 times, otherwise the next control + 1 bytes are copied.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i] as usize;
+        i += 1;
+        if control & 0x80 != 0 {
+            if let Some(byte) = data.get(i) {
+                out.extend(std::iter::repeat_n(*byte, (control & 0x7F) + 2));
+            }
+            i += 1;
+        } else {
+            let end = (i + control + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, sent: u8) -> u8 {
+        let mut reply = 0;
+        self.state = match self.state {
+            State::Magic if sent == 0x88 => State::MagicSecond,
+            State::Magic => State::Magic,
+            State::MagicSecond if sent == 0x33 => State::Command,
+            State::MagicSecond if sent == 0x88 => State::MagicSecond,
+            State::MagicSecond => State::Magic,
+            State::Command => {
+                self.command = sent;
+                self.checksum = sent as u16;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = sent & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(sent as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = sent as usize;
+                self.checksum = self.checksum.wrapping_add(sent as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (sent as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(sent as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.data.push(sent);
+                self.checksum = self.checksum.wrapping_add(sent as u16);
+                if self.data.len() == self.length {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.expected_checksum = sent as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.expected_checksum |= (sent as u16) << 8;
+                self.run_command();
+                State::Alive
+            }
+            State::Alive => {
+                reply = ALIVE;
+                State::Status
+            }
+            State::Status => {
+                reply = self.status;
+                State::Magic
+            }
+        };
+        reply
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use crate::cpu::memory_bus::MemoryBus;
+
+const WRAM: (u16, u16) = (0xC000, 0xDFFF);
+const HRAM: (u16, u16) = (0xFF80, 0xFFFE);
+
+/// A searchable byte : WRAM and HRAM by address, cartridge RAM by bank and address
+/// so banks that are switched out can still be compared.
+#[derive(Clone, Copy)]
+pub struct RamAddress {
+    pub bank: Option<u8>,
+    pub address: u16,
+}
+
+impl RamAddress {
+    fn read(&self, bus: &MemoryBus) -> u8 {
+        match self.bank {
+            Some(bank) => bus
+                .ram_bank(bank as usize)
+                .map_or(0, |ram| ram[(self.address - 0xA000) as usize]),
+            None => bus.memory[self.address as usize],
+        }
+    }
+}
+
+impl fmt::Display for RamAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.address),
+            None => write!(f, "   {:04X}", self.address),
+        }
+    }
+}
+
+/// How a byte must compare with its value at the previous search to survive.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u8),
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Option<Filter> {
+        match text {
+            "=" | "==" | "eq" | "equal" | "same" => Some(Filter::Equal),
+            "!=" | "ne" | "changed" => Some(Filter::Changed),
+            ">" | "inc" | "increased" => Some(Filter::Increased),
+            "<" | "dec" | "decreased" => Some(Filter::Decreased),
+            _ => crate::useful_func::parse_hex(text)
+                .filter(|value| *value <= 0xFF)
+                .map(|value| Filter::Value(value as u8)),
+        }
+    }
+
+    fn keeps(&self, previous: u8, current: u8) -> bool {
+        match self {
+            Filter::Equal => current == previous,
+            Filter::Changed => current != previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::Value(value) => current == *value,
+        }
+    }
+}
+
+pub struct Candidate {
+    pub location: RamAddress,
+    pub previous: u8,
+    pub current: u8,
+}
+
+/// Value search over WRAM, HRAM and cartridge RAM. `start` takes a snapshot, every
+/// `filter` drops the bytes that don't match and remembers the rest for the next one.
+pub struct RamSearch {
+    candidates: Vec<Candidate>,
+    started: bool,
+}
+
+impl RamSearch {
+    pub fn new() -> RamSearch {
+        RamSearch {
+            candidates: Vec::new(),
+            started: false,
+        }
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    pub fn start(&mut self, bus: &MemoryBus) {
+        let mut locations: Vec<RamAddress> = Vec::new();
+        for &(start, end) in [WRAM, HRAM].iter() {
+            locations.extend((start..=end).map(|address| RamAddress { bank: None, address }));
+        }
+        for bank in 0..bus.ram_bank_count() as u8 {
+            locations.extend((0xA000..=0xBFFF).map(|address| RamAddress {
+                bank: Some(bank),
+                address,
+            }));
+        }
+
+        self.candidates = locations
+            .into_iter()
+            .map(|location| {
+                let value = location.read(bus);
+                Candidate {
+                    location,
+                    previous: value,
+                    current: value,
+                }
+            })
+            .collect();
+        self.started = true;
+    }
+
+    /// Keeps the bytes passing `filter` and returns how many are left.
+    pub fn filter(&mut self, bus: &MemoryBus, filter: Filter) -> usize {
+        for candidate in self.candidates.iter_mut() {
+            candidate.previous = candidate.current;
+            candidate.current = candidate.location.read(bus);
+        }
+        self.candidates
+            .retain(|candidate| filter.keeps(candidate.previous, candidate.current));
+        self.candidates.len()
+    }
+}
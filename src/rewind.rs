@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of save states for rewinding.
+///
+/// Only the newest state is kept in full. Every older state is stored as the
+/// XOR of itself and the state after it, with the runs of zero bytes squeezed
+/// out, since most of memory doesn't change between two snapshots.
+pub struct RewindBuffer {
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+    interval: u32,
+    frames_since_capture: u32,
+}
+
+impl RewindBuffer {
+    /// Keeps up to `capacity` snapshots, taking one every `interval` frames.
+    pub fn new(capacity: usize, interval: u32) -> RewindBuffer {
+        RewindBuffer {
+            newest: None,
+            deltas: VecDeque::with_capacity(capacity),
+            capacity,
+            interval: interval.max(1),
+            frames_since_capture: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.frames_since_capture = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + if self.newest.is_some() { 1 } else { 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Call once per frame, returns true when a snapshot should be pushed.
+    pub fn frame_due(&mut self) -> bool {
+        self.frames_since_capture += 1;
+        if self.frames_since_capture >= self.interval {
+            self.frames_since_capture = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.newest.take() {
+            if previous.len() == state.len() {
+                self.deltas.push_back(encode_delta(&previous, &state));
+            } else {
+                // layout changed, history before this point can't be rebuilt.
+                self.deltas.clear();
+            }
+        }
+
+        while self.deltas.len() >= self.capacity {
+            self.deltas.pop_front();
+        }
+
+        self.newest = Some(state);
+    }
+
+    /// Takes the newest snapshot off the buffer, rebuilding the one before it.
+    /// The oldest snapshot is never removed, so holding rewind stops there.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.as_mut()?;
+        let state = newest.clone();
+
+        if let Some(delta) = self.deltas.pop_back() {
+            apply_delta(newest, &delta);
+        }
+
+        self.frames_since_capture = 0;
+        Some(state)
+    }
+}
+
+// Delta layout : repeated [zero run : varint][literal count : varint][literal bytes].
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < old.len() {
+        let zero_start = i;
+        while i < old.len() && old[i] == new[i] {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < old.len() && old[i] != new[i] {
+            i += 1;
+        }
+
+        write_varint(&mut out, literal_start - zero_start);
+        write_varint(&mut out, i - literal_start);
+        for j in literal_start..i {
+            out.push(old[j] ^ new[j]);
+        }
+    }
+    out
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+
+    while i < delta.len() {
+        pos += read_varint(delta, &mut i);
+        let literals = read_varint(delta, &mut i);
+        for _ in 0..literals {
+            state[pos] ^= delta[i];
+            pos += 1;
+            i += 1;
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
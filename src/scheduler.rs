@@ -0,0 +1,61 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Clock cycles the PPU takes for one full frame, 154 lines of 456 cycles.
+pub const CYCLES_PER_FRAME: u32 = 70224;
+/// 70224 cycles at 4.194304 MHz, i.e. 59.7275 frames a second.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+// sleep is coarse on some systems, so the last bit before a deadline is spent yielding.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+// further behind than this and we give up catching up instead of running a burst of frames.
+const MAX_LAG: Duration = Duration::from_millis(100);
+/// Paces frames against wall clock time.
+pub struct FrameScheduler {
+    period: Duration,
+    next_deadline: Instant,
+}
+
+impl FrameScheduler {
+    pub fn new() -> FrameScheduler {
+        FrameScheduler {
+            period: FRAME_DURATION,
+            next_deadline: Instant::now(),
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.period = FRAME_DURATION.div_f32(speed);
+        self.reset();
+    }
+
+    /// Forgets the schedule, e.g. after a pause, so the next frame is due right away.
+    pub fn reset(&mut self) {
+        self.next_deadline = Instant::now();
+    }
+
+    /// Blocks until the next frame is due.
+    pub fn wait(&mut self) {
+        let deadline = self.next_deadline;
+        let now = Instant::now();
+
+        if deadline > now {
+            let remaining = deadline - now;
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+            while Instant::now() < deadline {
+                thread::yield_now();
+            }
+        }
+
+        // schedule from the deadline rather than from now, so sleep overshoot
+        // doesn't add up over time.
+        self.next_deadline = deadline + self.period;
+
+        let now = Instant::now();
+        if now > self.next_deadline + MAX_LAG {
+            self.next_deadline = now + self.period;
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Blargg's test ROMs, run headless. They print their results over the serial
+//! port and end with "Passed" or "Failed". ROMs go in `<rom dir>/blargg/`, laid
+//! out like the original archive (see `common::rom_dir`).
+
+mod common;
+
+use common::BOOT_FRAMES;
+use emulator::headless::Headless;
+
+/// Runs until the serial output says how it went, or `max_frames` past the boot ROM.
+fn run_serial_test(rom: &str, max_frames: u64) {
+    let path = match common::find_rom(&format!("blargg/{}", rom)) {
+        Some(path) => path,
+        None => return,
+    };
+
+    let mut gb = Headless::new();
+    gb.load(&path).unwrap();
+
+    let mut output = String::new();
+    while gb.frames() < BOOT_FRAMES + max_frames {
+        gb.run_frame();
+        output.push_str(&String::from_utf8_lossy(&gb.take_serial_output()));
+        if output.contains("Passed") || output.contains("Failed") {
+            break;
+        }
+    }
+
+    assert!(
+        output.contains("Passed"),
+        "{} did not pass after {} frames, serial output :\n{}",
+        rom,
+        gb.frames(),
+        output
+    );
+}
+
+#[test]
+fn cpu_instrs_01_special() {
+    run_serial_test("cpu_instrs/individual/01-special.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_02_interrupts() {
+    run_serial_test("cpu_instrs/individual/02-interrupts.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_03_op_sp_hl() {
+    run_serial_test("cpu_instrs/individual/03-op sp,hl.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_04_op_r_imm() {
+    run_serial_test("cpu_instrs/individual/04-op r,imm.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_05_op_rp() {
+    run_serial_test("cpu_instrs/individual/05-op rp.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_06_ld_r_r() {
+    run_serial_test("cpu_instrs/individual/06-ld r,r.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_07_jr_jp_call_ret_rst() {
+    run_serial_test("cpu_instrs/individual/07-jr,jp,call,ret,rst.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_08_misc_instrs() {
+    run_serial_test("cpu_instrs/individual/08-misc instrs.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_09_op_r_r() {
+    run_serial_test("cpu_instrs/individual/09-op r,r.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_10_bit_ops() {
+    run_serial_test("cpu_instrs/individual/10-bit ops.gb", 2000);
+}
+
+#[test]
+fn cpu_instrs_11_op_a_hl() {
+    run_serial_test("cpu_instrs/individual/11-op a,(hl).gb", 2000);
+}
+
+#[test]
+fn cpu_instrs() {
+    run_serial_test("cpu_instrs/cpu_instrs.gb", 4000);
+}
+
+#[test]
+fn instr_timing() {
+    run_serial_test("instr_timing/instr_timing.gb", 500);
+}
@@ -0,0 +1,62 @@
+// every test binary compiles this module and none of them uses all of it.
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod json;
+
+// the boot ROM runs first and takes a good while to scroll the logo.
+pub const BOOT_FRAMES: u64 = 1000;
+
+/// Test ROMs are not part of the repository and nothing is downloaded. They are
+/// read from `$RIPBOY_TEST_ROMS`, or `tests/roms` when that isn't set.
+pub fn rom_dir() -> PathBuf {
+    match env::var_os("RIPBOY_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms"),
+    }
+}
+
+/// Once ROMs have been set up, through `$RIPBOY_TEST_ROMS` or a `tests/roms`
+/// directory, a missing one is a failure. Otherwise the test is skipped, so a
+/// plain `cargo test` still works without them.
+pub fn missing(what: &Path) {
+    if env::var_os("RIPBOY_TEST_ROMS").is_some() || rom_dir().is_dir() {
+        panic!("{} not found in {}", what.display(), rom_dir().display());
+    }
+    println!("skipping, {} not found", what.display());
+}
+
+/// Path of a test ROM under `rom_dir`, or None when the test is skipped.
+pub fn find_rom(relative: &str) -> Option<PathBuf> {
+    let path = rom_dir().join(relative);
+    if path.exists() {
+        Some(path)
+    } else {
+        missing(&path);
+        None
+    }
+}
+
+/// Every `.gb` file under `dir`, sorted so reports come out in a stable order.
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    find_files(dir, "gb")
+}
+
+pub fn find_files(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                files.extend(find_files(&path, extension));
+            } else if path.extension().is_some_and(|e| e == extension) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
@@ -0,0 +1,118 @@
+//! Two cores joined by the in-process link cable. The ROMs are the bundled
+//! homebrew ROM with a few instructions patched in after its header, so these
+//! need no test ROMs. The socket cable is tested on its own, byte by byte.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::BOOT_FRAMES;
+use emulator::headless::LinkedPair;
+
+/// Puts `byte` in SB, writes `control` to SC then loops forever with interrupts off.
+fn transfer_rom(name: &str, byte: u8, control: u8) -> PathBuf {
+    let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("retroid_[Home_brew_ROM].gb");
+    let mut rom = fs::read(source).unwrap();
+    let code = [
+        0xF3, // di
+        0x3E, byte, // ld a, byte
+        0xE0, 0x01, // ldh (SB), a
+        0x3E, control, // ld a, control
+        0xE0, 0x02, // ldh (SC), a
+        0x18, 0xFE, // jr -2
+    ];
+    // the entry point jumps to 0x150.
+    rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, rom).unwrap();
+    path
+}
+
+fn run(name: &str, first_control: u8, second_control: u8) -> LinkedPair {
+    let first = transfer_rom(&format!("{}-first.gb", name), 0x42, first_control);
+    let second = transfer_rom(&format!("{}-second.gb", name), 0x99, second_control);
+    let mut pair = LinkedPair::new();
+    pair.load(&first, &second).unwrap();
+    while pair.first.frames() < BOOT_FRAMES + 10 {
+        pair.run_frame();
+    }
+    pair
+}
+
+#[test]
+fn internal_clock_to_external_clock() {
+    let mut pair = run("internal-external", 0x81, 0x80);
+    for (gb, received) in [(&mut pair.first, 0x99), (&mut pair.second, 0x42)] {
+        assert_eq!(gb.peek(0xFF01), received);
+        assert_eq!(gb.peek(0xFF02) & 0x80, 0, "transfer still running");
+        assert_eq!(gb.peek(0xFF0F) & 0x08, 0x08, "no serial interrupt");
+    }
+}
+
+#[test]
+fn other_side_not_waiting() {
+    let mut pair = run("not-waiting", 0x81, 0x00);
+    assert_eq!(pair.first.peek(0xFF01), 0xFF);
+    assert_eq!(pair.first.peek(0xFF02) & 0x80, 0);
+    assert_eq!(pair.second.peek(0xFF01), 0x99);
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+
+    use emulator::{LinkCable, SerialDevice};
+
+    /// Both ends of a cable over a Unix socket in the test directory.
+    fn socket_pair(name: &str) -> (LinkCable, LinkCable) {
+        let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+        let _ = fs::remove_file(&path);
+        let address = path.to_str().unwrap().to_string();
+
+        let listening = {
+            let address = address.clone();
+            thread::spawn(move || LinkCable::listen(&address).unwrap())
+        };
+        let connected = loop {
+            match LinkCable::connect(&address) {
+                Ok(cable) => break cable,
+                Err(_) => thread::yield_now(),
+            }
+        };
+        (listening.join().unwrap(), connected)
+    }
+
+    /// Polls like a game waiting on the external clock until the other side clocks a byte.
+    fn wait_for_transfer(cable: &mut LinkCable, sent: u8) -> u8 {
+        loop {
+            if let Some(received) = cable.poll_external(Some(sent)) {
+                return received;
+            }
+        }
+    }
+
+    #[test]
+    fn socket_transfer() {
+        let (mut first, mut second) = socket_pair("socket-transfer.sock");
+        let other = thread::spawn(move || wait_for_transfer(&mut second, 0x99));
+        assert_eq!(first.exchange(0x42), 0x99);
+        assert_eq!(other.join().unwrap(), 0x42);
+    }
+
+    #[test]
+    fn late_reply_is_dropped() {
+        let (mut first, mut second) = socket_pair("socket-late-reply.sock");
+        // nobody is polling, this times out.
+        assert_eq!(first.exchange(0x42), 0xFF);
+        // the reply to it only goes out now, and must not answer the next transfer.
+        assert_eq!(wait_for_transfer(&mut second, 0x11), 0x42);
+
+        let other = thread::spawn(move || wait_for_transfer(&mut second, 0x99));
+        assert_eq!(first.exchange(0x43), 0x99);
+        assert_eq!(other.join().unwrap(), 0x43);
+    }
+}
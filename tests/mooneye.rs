@@ -0,0 +1,101 @@
+//! The Mooneye GB acceptance suite, run headless. A test ends on `LD B,B` and has
+//! passed when B, C, D, E, H and L hold the Fibonacci numbers 3, 5, 8, 13, 21 and 34.
+//! ROMs go in `<rom dir>/mooneye/acceptance/` (see `common::rom_dir`).
+
+mod common;
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use common::BOOT_FRAMES;
+use emulator::headless::Headless;
+
+const LD_B_B: u8 = 0x40;
+// frames a test gets after the boot ROM, the slowest ones need a few seconds.
+const MAX_FRAMES: u64 = 600;
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+enum Outcome {
+    Passed,
+    Failed([u8; 6]),
+    TimedOut,
+    Panicked,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed(r) => write!(
+                f,
+                "FAILED B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+                r[0], r[1], r[2], r[3], r[4], r[5]
+            ),
+            Outcome::TimedOut => write!(f, "TIMED OUT"),
+            Outcome::Panicked => write!(f, "PANICKED"),
+        }
+    }
+}
+
+/// Tests for other models are named `<test>-<models>`, e.g. `-cgb` or `-S`.
+/// `dmgABC` and the `G` group cover the DMG this emulates.
+fn runs_on_dmg(path: &Path) -> bool {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match stem.rsplit_once('-') {
+        Some((_, models)) => {
+            models.contains("dmgABC")
+                || (models.contains('G') && models.chars().all(|c| c.is_ascii_uppercase()))
+        }
+        None => true,
+    }
+}
+
+fn run_test(path: &Path) -> (Outcome, u64) {
+    let mut frames = 0;
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut gb = Headless::new();
+        gb.load(path).unwrap();
+        let stopped = gb.run_until_opcode(LD_B_B, BOOT_FRAMES + MAX_FRAMES);
+        frames = gb.frames();
+        if !stopped {
+            return Outcome::TimedOut;
+        }
+
+        let r = gb.registers();
+        let values = [r.b, r.c, r.d, r.e, r.h, r.l];
+        if values == FIBONACCI {
+            Outcome::Passed
+        } else {
+            Outcome::Failed(values)
+        }
+    }));
+    (outcome.unwrap_or(Outcome::Panicked), frames)
+}
+
+#[test]
+fn acceptance() {
+    let dir = common::rom_dir().join("mooneye").join("acceptance");
+    let roms: Vec<_> = common::find_roms(&dir)
+        .into_iter()
+        .filter(|path| runs_on_dmg(path))
+        .collect();
+    if roms.is_empty() {
+        common::missing(&dir);
+        return;
+    }
+
+    let mut failed = 0;
+    println!("{:<48} {:>6}  result", "test", "frames");
+    for rom in roms.iter() {
+        let name = rom.strip_prefix(&dir).unwrap_or(rom).display().to_string();
+        let (outcome, frames) = run_test(rom);
+        if !matches!(outcome, Outcome::Passed) {
+            failed += 1;
+        }
+        println!("{:<48} {:>6}  {}", name, frames, outcome);
+    }
+    println!("{} of {} passed", roms.len() - failed, roms.len());
+
+    assert_eq!(failed, 0, "{} of {} Mooneye tests failed", failed, roms.len());
+}
@@ -0,0 +1,121 @@
+//! Screenshot tests for the PPU. A rendering test ROM runs for a fixed number of
+//! frames and the screen is compared pixel by pixel with a reference in
+//! `tests/reference/`. References are the test's own published screenshot as a
+//! binary greyscale PGM, e.g. `magick reference-dmg.png -colorspace gray dmg-acid2.pgm`,
+//! and are compared as shades 0-3 so changing the display palette doesn't break them.
+//! On a mismatch the screen and a diff go to `target/tmp/<name>.{actual,diff}.png`.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::BOOT_FRAMES;
+use emulator::headless::Headless;
+use emulator::png;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+const SHADES: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+const MISMATCH: u32 = 0xFF0000;
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("reference")
+        .join(format!("{}.pgm", name))
+}
+
+/// A binary PGM (P5) as shades, white 0 and black 3 : `P5`, width, height and
+/// the maximum grey, then a byte per pixel.
+fn read_pgm(path: &Path) -> Vec<u8> {
+    let data = fs::read(path).unwrap_or_else(|error| panic!("{} : {}", path.display(), error));
+    let mut header = Vec::new();
+    let mut at = 0;
+    while header.len() < 4 {
+        while data.get(at).is_some_and(u8::is_ascii_whitespace) {
+            at += 1;
+        }
+        if data.get(at) == Some(&b'#') {
+            while data.get(at).is_some_and(|c| *c != b'\n') {
+                at += 1;
+            }
+            continue;
+        }
+        let start = at;
+        while data.get(at).is_some_and(|c| !c.is_ascii_whitespace()) {
+            at += 1;
+        }
+        assert!(at > start, "{} ends in its header", path.display());
+        header.push(String::from_utf8_lossy(&data[start..at]).into_owned());
+    }
+
+    assert_eq!(header[0], "P5", "{} is not a binary PGM", path.display());
+    let size: Vec<usize> = header[1..].iter().map(|n| n.parse().unwrap()).collect();
+    assert_eq!(size[..2], [WIDTH, HEIGHT], "{} should be 160x144", path.display());
+    let max = size[2];
+    assert!(max > 0 && max < 256, "{} should have 8-bit greys", path.display());
+
+    // one whitespace byte ends the header.
+    let pixels = data.get(at + 1..at + 1 + WIDTH * HEIGHT).expect("PGM ends early");
+    pixels
+        .iter()
+        .map(|level| ((max.saturating_sub(*level as usize) * 3 + max / 2) / max) as u8)
+        .collect()
+}
+
+fn to_image(shades: &[u8]) -> Vec<u32> {
+    shades.iter().map(|shade| SHADES[*shade as usize & 3]).collect()
+}
+
+fn screenshot_test(rom: &str, name: &str, frames: u64) {
+    let path = match common::find_rom(rom) {
+        Some(path) => path,
+        None => return,
+    };
+
+    let mut gb = Headless::new();
+    gb.load(&path).unwrap();
+    while gb.frames() < BOOT_FRAMES + frames {
+        gb.run_frame();
+    }
+    let actual = gb.screen_shades();
+
+    let reference = reference_path(name);
+    assert!(
+        reference.exists(),
+        "{} is missing, convert the ROM's published screenshot to it",
+        reference.display()
+    );
+
+    let expected = read_pgm(&reference);
+    let different = expected.iter().zip(actual.iter()).filter(|(e, a)| e != a).count();
+    if different == 0 {
+        return;
+    }
+
+    // matching pixels faded, different ones in red.
+    let diff: Vec<u32> = expected
+        .iter()
+        .zip(actual.iter())
+        .map(|(e, a)| if e == a { SHADES[*e as usize & 3] | 0x808080 } else { MISMATCH })
+        .collect();
+    let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let actual_png = out.join(format!("{}.actual.png", name));
+    let diff_png = out.join(format!("{}.diff.png", name));
+    png::write(&actual_png, WIDTH, HEIGHT, &to_image(&actual)).unwrap();
+    png::write(&diff_png, WIDTH, HEIGHT, &diff).unwrap();
+
+    panic!(
+        "{} pixels differ from {}, see {} and {}",
+        different,
+        reference.display(),
+        actual_png.display(),
+        diff_png.display()
+    );
+}
+
+#[test]
+fn dmg_acid2() {
+    screenshot_test("dmg-acid2.gb", "dmg-acid2", 60);
+}
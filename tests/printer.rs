@@ -0,0 +1,75 @@
+//! The Game Boy Printer fed packets byte by byte, the way a game sends them.
+
+use std::fs;
+use std::iter::repeat_n;
+use std::path::{Path, PathBuf};
+
+use emulator::{Printer, SerialDevice};
+
+fn out_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Sends one packet, returns the two bytes the printer answers at the end.
+fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+    let mut packet = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+    packet.extend_from_slice(data);
+    let checksum = packet.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+
+    for byte in [0x88, 0x33].iter().chain(packet.iter()).chain(checksum.to_le_bytes().iter()) {
+        assert_eq!(printer.exchange(*byte), 0);
+    }
+    (printer.exchange(0), printer.exchange(0))
+}
+
+/// Two rows of tiles, every tile a different pattern : eight bytes of its
+/// number then eight of 0xF0.
+fn image() -> Vec<u8> {
+    (0..40u8).flat_map(|tile| repeat_n(tile, 8).chain(repeat_n(0xF0, 8))).collect()
+}
+
+fn print_image(dir: &Path, data_packets: &[(bool, Vec<u8>)]) -> Vec<u8> {
+    let mut printer = Printer::new(dir);
+    assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0x00));
+    for (compressed, data) in data_packets {
+        assert_eq!(send(&mut printer, 0x04, *compressed, data), (0x81, 0x08));
+    }
+    assert_eq!(send(&mut printer, 0x04, false, &[]).0, 0x81);
+    assert_eq!(send(&mut printer, 0x02, false, &[0x01, 0x13, 0xE4, 0x40]), (0x81, 0x02));
+    assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, 0x02));
+
+    assert_eq!(printer.pages(), [dir.join("print_1.png")]);
+    fs::read(&printer.pages()[0]).unwrap()
+}
+
+#[test]
+fn prints_to_png() {
+    let png = print_image(&out_dir("printer-plain"), &[(false, image())]);
+    // IHDR width and height.
+    assert_eq!(&png[16..24], [0, 0, 0, 160, 0, 0, 0, 16]);
+}
+
+#[test]
+fn compressed_data_prints_the_same() {
+    let mut compressed = Vec::new();
+    for tile in 0..40 {
+        // one literal byte, a run of 7 and a run of 8.
+        compressed.extend_from_slice(&[0x00, tile, 0x80 | 5, tile, 0x80 | 6, 0xF0]);
+    }
+
+    let plain = print_image(&out_dir("printer-plain-2"), &[(false, image())]);
+    let packed = print_image(&out_dir("printer-compressed"), &[(true, compressed)]);
+    assert_eq!(plain, packed);
+}
+
+#[test]
+fn bad_checksum() {
+    let mut printer = Printer::new(&out_dir("printer-checksum"));
+    for byte in [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x12, 0x34] {
+        printer.exchange(byte);
+    }
+    assert_eq!((printer.exchange(0), printer.exchange(0)), (0x81, 0x01));
+}
@@ -0,0 +1,185 @@
+//! Single-step tests for the CPU, using the published SM83 JSON vectors : every
+//! test gives the registers and RAM before and after one instruction, plus the
+//! memory access made on each M-cycle. The vectors go in `<rom dir>/sm83/`, e.g.
+//! `sm83/v1/00.json` and `sm83/v1/cb 00.json` (see `common::rom_dir`).
+//!
+//! Registers, RAM and the cycle count have to match. The emulator isn't M-cycle
+//! accurate, so accesses that differ are only counted in the table.
+//! There are a lot of vectors, `cargo test --release --test sm83` is much quicker.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::json::{self, Json};
+use emulator::headless::{CpuState, FlatCpu};
+
+fn number(state: &Json, key: &str) -> u64 {
+    state.get(key).and_then(Json::as_u64).unwrap_or(0)
+}
+
+fn read_state(state: &Json) -> CpuState {
+    CpuState {
+        a: number(state, "a") as u8,
+        b: number(state, "b") as u8,
+        c: number(state, "c") as u8,
+        d: number(state, "d") as u8,
+        e: number(state, "e") as u8,
+        f: number(state, "f") as u8,
+        h: number(state, "h") as u8,
+        l: number(state, "l") as u8,
+        sp: number(state, "sp") as u16,
+        pc: number(state, "pc") as u16,
+        ime: number(state, "ime") != 0,
+    }
+}
+
+/// `[address, value]` pairs.
+fn read_ram(state: &Json) -> Vec<(u16, u8)> {
+    state
+        .get("ram")
+        .map_or(&[][..], Json::as_array)
+        .iter()
+        .map(|pair| {
+            let pair = pair.as_array();
+            let address = pair.first().and_then(Json::as_u64).unwrap_or(0);
+            let value = pair.get(1).and_then(Json::as_u64).unwrap_or(0);
+            (address as u16, value as u8)
+        })
+        .collect()
+}
+
+/// One entry per M-cycle, null or `[address, value, kind]` where the kind tells
+/// reads from writes.
+fn read_accesses(cycles: &[Json]) -> Vec<(u16, u8, bool)> {
+    cycles
+        .iter()
+        .filter_map(|cycle| {
+            let cycle = cycle.as_array();
+            let kind = cycle.get(2).and_then(Json::as_str)?;
+            let write = kind.contains('w');
+            if !write && !kind.contains('r') {
+                return None;
+            }
+            let address = cycle.first().and_then(Json::as_u64)? as u16;
+            let value = cycle.get(1).and_then(Json::as_u64)? as u8;
+            Some((address, value, write))
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct FileResult {
+    tests: usize,
+    state_failures: usize,
+    cycle_failures: usize,
+    access_failures: usize,
+    first_failure: Option<String>,
+}
+
+fn run_file(cpu: &mut FlatCpu, path: &Path) -> FileResult {
+    let text = fs::read_to_string(path).unwrap();
+    let tests = json::parse(&text).unwrap_or_else(|error| panic!("{} : {}", path.display(), error));
+    let mut result = FileResult::default();
+
+    for test in tests.as_array() {
+        let name = test.get("name").and_then(Json::as_str).unwrap_or("?");
+        let (initial, expected) = match (test.get("initial"), test.get("final")) {
+            (Some(initial), Some(expected)) => (initial, expected),
+            _ => continue,
+        };
+        result.tests += 1;
+
+        cpu.set_state(&read_state(initial));
+        for (address, value) in read_ram(initial) {
+            cpu.ram()[address as usize] = value;
+        }
+        let cycles = cpu.step();
+
+        let want = read_state(expected);
+        let got = cpu.state();
+        let mut problems = Vec::new();
+        if got != want {
+            problems.push(format!("registers\n    want {:?}\n    got  {:?}", want, got));
+        }
+        for (address, value) in read_ram(expected) {
+            let actual = cpu.ram()[address as usize];
+            if actual != value {
+                problems.push(format!("{:04X} = {:02X}, want {:02X}", address, actual, value));
+            }
+        }
+        if !problems.is_empty() {
+            result.state_failures += 1;
+        }
+
+        let expected_cycles = test.get("cycles").map_or(&[][..], Json::as_array);
+        if cycles != expected_cycles.len() as u32 * 4 {
+            result.cycle_failures += 1;
+            problems.push(format!("{} cycles, want {}", cycles, expected_cycles.len() * 4));
+        }
+        if cpu.accesses() != &read_accesses(expected_cycles)[..] {
+            result.access_failures += 1;
+        }
+
+        if !problems.is_empty() && result.first_failure.is_none() {
+            result.first_failure = Some(format!("{} : {}", name, problems.join(", ")));
+        }
+    }
+    result
+}
+
+/// Needs no vectors : EI on the last byte of memory wraps PC to 0, and only the
+/// opcode fetches show up as accesses, not the CPU checking when IME comes on.
+#[test]
+fn ei_at_top_of_memory() {
+    let mut cpu = FlatCpu::new();
+    cpu.set_state(&CpuState {
+        pc: 0xFFFF,
+        ..CpuState::default()
+    });
+    cpu.ram()[0xFFFF] = 0xFB;
+    cpu.ram()[0x0000] = 0x00;
+
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.accesses(), [(0xFFFF, 0xFB, false)]);
+    assert!(!cpu.state().ime);
+
+    cpu.step();
+    assert_eq!(cpu.accesses(), [(0x0000, 0x00, false)]);
+    assert!(cpu.state().ime);
+}
+
+#[test]
+fn single_step() {
+    let dir = common::rom_dir().join("sm83");
+    let files = common::find_files(&dir, "json");
+    if files.is_empty() {
+        common::missing(&dir);
+        return;
+    }
+
+    let mut cpu = FlatCpu::new();
+    let mut failed_files = Vec::new();
+    println!("{:<16} {:>6} {:>8} {:>8} {:>8}", "file", "tests", "state", "cycles", "accesses");
+    for path in files.iter() {
+        let name = path.strip_prefix(&dir).unwrap_or(path).display().to_string();
+        let result = run_file(&mut cpu, path);
+        println!(
+            "{:<16} {:>6} {:>8} {:>8} {:>8}",
+            name, result.tests, result.state_failures, result.cycle_failures, result.access_failures
+        );
+        if let Some(failure) = result.first_failure {
+            println!("    first failure, {}", failure);
+            failed_files.push(name);
+        }
+    }
+
+    assert!(
+        failed_files.is_empty(),
+        "{} of {} opcode files failed : {}",
+        failed_files.len(),
+        files.len(),
+        failed_files.join(", ")
+    );
+}